@@ -0,0 +1,210 @@
+//! Migration file names and `-- migrator:` directives, parsed by fuzion-commons at runtime and
+//! checked by its macros at compile time with the same rules.
+
+use std::time::Duration;
+
+pub type FileVersion = (i16, i16, i16);
+
+pub const NO_TRANSACTION_DIRECTIVE: &str = "-- migrator:no-transaction";
+
+pub const REQUIRES_DIRECTIVE: &str = "-- migrator:requires ";
+
+pub const LOCK_TIMEOUT_DIRECTIVE: &str = "-- migrator:lock-timeout ";
+
+pub const STATEMENT_TIMEOUT_DIRECTIVE: &str = "-- migrator:statement-timeout ";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Directive<'a> {
+  Requires(&'a str, FileVersion),
+  LockTimeout(Duration),
+  StatementTimeout(Duration),
+}
+
+/// `v<major>_<minor>_<patch>.sql`
+pub fn parse_filename(file_name: &str) -> Option<FileVersion> {
+  let mut parts = file_name
+    .strip_prefix('v')?
+    .strip_suffix(".sql")?
+    .split('_');
+
+  let mut next = || component(parts.next()?);
+
+  let version = (next()?, next()?, next()?);
+
+  match parts.next() {
+    Some(_) => None,
+    None => Some(version),
+  }
+}
+
+/// `<major>.<minor>.<patch>`, without pre-release or build metadata.
+pub fn parse_version(version: &str) -> Option<FileVersion> {
+  let mut parts = version.split('.');
+
+  let mut next = || component(parts.next()?);
+
+  let version = (next()?, next()?, next()?);
+
+  match parts.next() {
+    Some(_) => None,
+    None => Some(version),
+  }
+}
+
+fn component(part: &str) -> Option<i16> {
+  match !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()) {
+    true => part.parse().ok(),
+    false => None,
+  }
+}
+
+/// `<amount><unit>`, with a unit of `ms`, `s`, `min` or `h`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+  let split = value
+    .find(|c: char| !c.is_ascii_digit())
+    .unwrap_or(value.len());
+  let amount = value[..split].parse::<u64>().ok()?;
+
+  match &value[split..] {
+    "ms" => Some(Duration::from_millis(amount)),
+    "s" => Some(Duration::from_secs(amount)),
+    "min" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+    "h" => Some(Duration::from_secs(amount.checked_mul(3600)?)),
+    _ => None,
+  }
+}
+
+/// Splits a migration on its `-- down` marker line, returning the up and down SQL.
+pub fn split_down_section(query: &str) -> (&str, Option<&str>) {
+  let mut offset = 0;
+
+  for line in query.split_inclusive('\n') {
+    if line.trim().eq_ignore_ascii_case("-- down") {
+      return (&query[..offset], Some(&query[offset + line.len()..]));
+    }
+
+    offset += line.len();
+  }
+
+  (query, None)
+}
+
+pub fn has_no_transaction_directive(query: &str) -> bool {
+  query
+    .lines()
+    .any(|line| line.trim().eq_ignore_ascii_case(NO_TRANSACTION_DIRECTIVE))
+}
+
+/// The `requires` and timeout directives of a section, failing with the first malformed
+/// directive line.
+pub fn parse_directives(query: &str) -> Result<Vec<Directive<'_>>, &str> {
+  query
+    .lines()
+    .map(str::trim)
+    .filter_map(|line| {
+      let directive = match (
+        line.strip_prefix(REQUIRES_DIRECTIVE),
+        line.strip_prefix(LOCK_TIMEOUT_DIRECTIVE),
+        line.strip_prefix(STATEMENT_TIMEOUT_DIRECTIVE),
+      ) {
+        (Some(requirement), _, _) => parse_requirement(requirement),
+        (_, Some(value), _) => parse_duration(value.trim()).map(Directive::LockTimeout),
+        (_, _, Some(value)) => parse_duration(value.trim()).map(Directive::StatementTimeout),
+        _ => return None,
+      };
+
+      Some(directive.ok_or(line))
+    })
+    .collect()
+}
+
+/// `<module> [>=] <major>.<minor>.<patch>`
+fn parse_requirement(requirement: &str) -> Option<Directive<'_>> {
+  let mut parts = requirement.split_whitespace();
+  let module = parts.next()?;
+  let version = match parts.next()? {
+    ">=" => parts.next()?,
+    version => version,
+  };
+
+  match parts.next() {
+    Some(_) => None,
+    None => Some(Directive::Requires(module, parse_version(version)?)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{parse_directives, parse_filename, split_down_section, Directive};
+
+  #[test]
+  fn parse_filename_valid() {
+    assert_eq!(parse_filename("v1_2_3.sql"), Some((1, 2, 3)));
+    assert_eq!(parse_filename("v0_10_0.sql"), Some((0, 10, 0)));
+  }
+
+  #[test]
+  fn parse_filename_malformed() {
+    assert_eq!(parse_filename("1_2_3.sql"), None);
+    assert_eq!(parse_filename("v1_2.sql"), None);
+    assert_eq!(parse_filename("v1_2_3_4.sql"), None);
+    assert_eq!(parse_filename("v1_2_x.sql"), None);
+    assert_eq!(parse_filename("v1__3.sql"), None);
+    assert_eq!(parse_filename("v1_2_99999.sql"), None);
+  }
+
+  #[test]
+  fn split_down_section_on_marker() {
+    let cases = [
+      ("CREATE TABLE a ();\n", ("CREATE TABLE a ();\n", None)),
+      (
+        "CREATE TABLE a ();\n-- down\nDROP TABLE a;\n",
+        ("CREATE TABLE a ();\n", Some("DROP TABLE a;\n")),
+      ),
+      (
+        "CREATE TABLE a ();\r\n  -- DOWN \r\nDROP TABLE a;",
+        ("CREATE TABLE a ();\r\n", Some("DROP TABLE a;")),
+      ),
+      ("-- down\nDROP TABLE a;", ("", Some("DROP TABLE a;"))),
+      (
+        "CREATE TABLE a ();\n-- down",
+        ("CREATE TABLE a ();\n", Some("")),
+      ),
+      (
+        "CREATE TABLE a ();\n-- down\nDROP TABLE a;\n-- down\n",
+        ("CREATE TABLE a ();\n", Some("DROP TABLE a;\n-- down\n")),
+      ),
+      // Only a line of its own is a marker.
+      (
+        "-- downtime expected\nSELECT 1;",
+        ("-- downtime expected\nSELECT 1;", None),
+      ),
+      ("SELECT 1; -- down\n", ("SELECT 1; -- down\n", None)),
+    ];
+
+    for (query, split) in cases {
+      assert_eq!(split_down_section(query), split, "{query:?}");
+    }
+  }
+
+  #[test]
+  fn directives_of_section() {
+    assert_eq!(
+      parse_directives(
+        "-- migrator:requires billing >= 1.2.0\n  -- migrator:lock-timeout 5s  \nSELECT 1;\n\
+         -- migrator:statement-timeout 2min\n-- migrator:no-transaction"
+      ),
+      Ok(vec![
+        Directive::Requires("billing", (1, 2, 0)),
+        Directive::LockTimeout(Duration::from_secs(5)),
+        Directive::StatementTimeout(Duration::from_secs(120)),
+      ])
+    );
+    assert_eq!(
+      parse_directives("SELECT 1;\n  -- migrator:requires billing >= 1.2  \n"),
+      Err("-- migrator:requires billing >= 1.2")
+    );
+  }
+}
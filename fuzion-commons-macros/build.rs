@@ -0,0 +1,19 @@
+use std::process::Command;
+
+// Cargo only sets `RUSTC` for build scripts, not for the proc macros it loads into the compiler,
+// so the version is captured here and compiled into the macros crate.
+fn main() {
+  let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+  let version = Command::new(rustc)
+    .arg("--version")
+    .output()
+    .ok()
+    .filter(|output| output.status.success())
+    .and_then(|output| String::from_utf8(output.stdout).ok());
+
+  if let Some(version) = version {
+    println!("cargo:rustc-env=FUZION_RUSTC_VERSION={}", version.trim());
+  }
+
+  println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,387 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fuzion_commons_directives::{
+  parse_directives, parse_filename, split_down_section, FileVersion,
+};
+use proc_macro::{TokenStream, TokenTree};
+
+/// Embeds every `v<major>_<minor>_<patch>.sql` file in a directory as a
+/// `Vec<Box<dyn Migration>>`, sorted by version.
+///
+/// The path is relative to the invoking crate's manifest directory. Malformed
+/// `.sql` file names or `-- migrator:` directives and duplicate versions fail
+/// compilation. Files added to the directory are only picked up once the
+/// invoking crate is rebuilt.
+///
+/// ```ignore
+/// let migrations = fuzion_commons::migration::embed_migrations!("migrations");
+/// ```
+#[proc_macro]
+pub fn embed_migrations(input: TokenStream) -> TokenStream {
+  match expand(input) {
+    Ok(output) => output.parse().unwrap(),
+    Err(err) => format!("compile_error!({err:?})").parse().unwrap(),
+  }
+}
+
+fn expand(input: TokenStream) -> Result<String, String> {
+  let relative_dir = parse_path(input)?;
+  let dir = match std::env::var_os("CARGO_MANIFEST_DIR") {
+    Some(manifest_dir) => PathBuf::from(manifest_dir).join(&relative_dir),
+    None => PathBuf::from(&relative_dir),
+  };
+
+  let mut migrations = read_migrations(&dir)?;
+  migrations.sort_by_key(|(version, _)| *version);
+
+  for (_, path) in &migrations {
+    check_migration(path)?;
+  }
+
+  for pair in migrations.windows(2) {
+    if pair[0].0 == pair[1].0 {
+      return Err(format!(
+        "duplicate migration version {}.{}.{}: {} and {}",
+        pair[0].0 .0,
+        pair[0].0 .1,
+        pair[0].0 .2,
+        pair[0].1.display(),
+        pair[1].1.display(),
+      ));
+    }
+  }
+
+  let items = migrations
+    .iter()
+    .map(|((major, minor, patch), path)| {
+      // Shown in plans and lint findings, relative like the path given to the macro.
+      let source = Path::new(&relative_dir).join(path.file_name().unwrap_or_default());
+
+      format!(
+        "::std::boxed::Box::new(::fuzion_commons::migration::PlainMigration::new(\
+         ::fuzion_commons::version::Version::new({major}, {minor}, {patch}), \
+         ::std::include_str!({:?})).with_source({:?})) \
+         as ::std::boxed::Box<dyn ::fuzion_commons::migration::Migration>",
+        path.display().to_string(),
+        source.display().to_string(),
+      )
+    })
+    .collect::<Vec<_>>()
+    .join(", ");
+
+  Ok(format!("::std::vec![{items}]"))
+}
+
+/// Embeds a single `v<major>_<minor>_<patch>.sql` file as a
+/// `Box<PlainMigration>`.
+///
+/// The path is relative to the invoking file, as with `include_str!`. A
+/// malformed file name or `-- migrator:` directive fails compilation.
+///
+/// ```ignore
+/// let migration = fuzion_commons::plain_migration!("../migrations/v1_0_0.sql");
+/// ```
+#[proc_macro]
+pub fn plain_migration(input: TokenStream) -> TokenStream {
+  match expand_plain_migration(input) {
+    Ok(output) => output.parse().unwrap(),
+    Err(err) => format!("compile_error!({err:?})").parse().unwrap(),
+  }
+}
+
+fn expand_plain_migration(input: TokenStream) -> Result<String, String> {
+  let span = input.clone().into_iter().next().map(|token| token.span());
+  let relative = parse_path(input)?;
+
+  let path = span
+    .and_then(|span| span.local_file())
+    .and_then(|file| Some(file.parent()?.join(&relative)))
+    .ok_or_else(|| format!("could not locate {relative}"))?;
+
+  let (major, minor, patch) = path
+    .file_name()
+    .and_then(|name| name.to_str())
+    .and_then(parse_filename)
+    .ok_or_else(|| {
+      format!("malformed migration file name {relative}, expected v<major>_<minor>_<patch>.sql")
+    })?;
+
+  check_migration(&path)?;
+
+  let path = path
+    .canonicalize()
+    .map_err(|err| format!("could not read {relative}: {err}"))?;
+
+  Ok(format!(
+    "::std::boxed::Box::new(::fuzion_commons::migration::PlainMigration::new(\
+     ::fuzion_commons::version::Version::new({major}, {minor}, {patch}), \
+     ::std::include_str!({:?})).with_source({relative:?}))",
+    path.display().to_string(),
+  ))
+}
+
+/// Captures the invoking crate's name, version, git commit, dirty flag, rustc version and build
+/// time as a `fuzion_commons::version::BuildInfo`.
+///
+/// The git details are `None` outside of a work tree. The build time honors
+/// `SOURCE_DATE_EPOCH`. The invoking crate is rebuilt when `HEAD` moves, but uncommitted edits to
+/// files outside of it are only reflected once it is rebuilt for another reason.
+///
+/// ```ignore
+/// let build_info = fuzion_commons::version::build_info!();
+/// ```
+#[proc_macro]
+pub fn build_info(input: TokenStream) -> TokenStream {
+  match expand_build_info(input) {
+    Ok(output) => output.parse().unwrap(),
+    Err(err) => format!("compile_error!({err:?})").parse().unwrap(),
+  }
+}
+
+fn expand_build_info(input: TokenStream) -> Result<String, String> {
+  if !input.is_empty() {
+    return Err(String::from("build_info! takes no arguments"));
+  }
+
+  let env = |name: &str| std::env::var(name).map_err(|_| format!("{name} is not set"));
+  let component = |name: &str| {
+    env(name)?
+      .parse::<i16>()
+      .map_err(|err| format!("{name}: {err}"))
+  };
+
+  let manifest_dir = PathBuf::from(env("CARGO_MANIFEST_DIR")?);
+  let crate_name = env("CARGO_PKG_NAME")?;
+  let (major, minor, patch) = (
+    component("CARGO_PKG_VERSION_MAJOR")?,
+    component("CARGO_PKG_VERSION_MINOR")?,
+    component("CARGO_PKG_VERSION_PATCH")?,
+  );
+
+  let git =
+    |args: &[&str]| command_output(Command::new("git").arg("-C").arg(&manifest_dir).args(args));
+  let git_commit = git(&["rev-parse", "HEAD"]);
+  let git_dirty = git_commit
+    .as_ref()
+    .and_then(|_| git(&["status", "--porcelain", "--untracked-files=no"]))
+    .map(|status| !status.is_empty());
+
+  let rustc_version = option_env!("FUZION_RUSTC_VERSION");
+
+  let built_at = match std::env::var("SOURCE_DATE_EPOCH") {
+    Ok(epoch) => epoch
+      .parse()
+      .map_err(|err| format!("SOURCE_DATE_EPOCH: {err}"))?,
+    Err(_) => SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_err(|err| err.to_string())?
+      .as_secs(),
+  };
+
+  // Depending on the files `HEAD` resolves through makes cargo rebuild the invoking crate on
+  // commits and checkouts. Refs are moved to `packed-refs` by `git gc` and `git pack-refs`.
+  let tracked = git(&["rev-parse", "--absolute-git-dir"])
+    .map(PathBuf::from)
+    .map(|git_dir| {
+      let head = git_dir.join("HEAD");
+      let reference = std::fs::read_to_string(&head)
+        .ok()
+        .and_then(|head| Some(git_dir.join(head.trim().strip_prefix("ref: ")?)));
+
+      std::iter::once(head)
+        .chain(reference)
+        .chain(std::iter::once(git_dir.join("packed-refs")))
+        .filter(|path| path.is_file())
+        .map(|path| {
+          format!(
+            "const _: &[u8] = ::std::include_bytes!({:?});",
+            path.display().to_string()
+          )
+        })
+        .collect::<String>()
+    })
+    .unwrap_or_default();
+
+  Ok(format!(
+    "{{ {tracked} ::fuzion_commons::version::BuildInfo {{ \
+     crate_name: {crate_name:?}, \
+     version: ::fuzion_commons::version::ModuleVersion::new({crate_name:?}, {major}, {minor}, {patch}), \
+     git_commit: {}, \
+     git_dirty: {}, \
+     rustc_version: {}, \
+     built_at: {:?} }} }}",
+    option(git_commit.as_deref().map(|commit| format!("{commit:?}"))),
+    option(git_dirty.map(|dirty| dirty.to_string())),
+    option(rustc_version.map(|version| format!("{version:?}"))),
+    rfc3339(built_at),
+  ))
+}
+
+/// Trimmed stdout of a successful command.
+fn command_output(command: &mut Command) -> Option<String> {
+  let output = command.output().ok()?;
+
+  match output.status.success() {
+    true => Some(String::from_utf8(output.stdout).ok()?.trim().to_owned()),
+    false => None,
+  }
+}
+
+fn option(value: Option<String>) -> String {
+  match value {
+    Some(value) => format!("::std::option::Option::Some({value})"),
+    None => String::from("::std::option::Option::None"),
+  }
+}
+
+/// Formats seconds since the epoch as a UTC timestamp, e.g. `2024-05-01T12:00:00Z`.
+fn rfc3339(secs: u64) -> String {
+  // Days to civil date, from Howard Hinnant's date algorithms.
+  let days = (secs / 86_400) as i64 + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days - era * 146_097;
+  let year_of_era =
+    (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+  let month = match month_index < 10 {
+    true => month_index + 3,
+    false => month_index - 9,
+  };
+  let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+  let secs_of_day = secs % 86_400;
+
+  format!(
+    "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+    secs_of_day / 3_600,
+    secs_of_day % 3_600 / 60,
+    secs_of_day % 60,
+  )
+}
+
+fn parse_path(input: TokenStream) -> Result<String, String> {
+  let mut tokens = input.into_iter();
+
+  let path = match (tokens.next(), tokens.next()) {
+    (Some(TokenTree::Literal(literal)), None) => literal.to_string(),
+    _ => return Err(String::from("expected a single string literal path")),
+  };
+
+  match path
+    .strip_prefix('"')
+    .and_then(|path| path.strip_suffix('"'))
+  {
+    Some(path) if !path.contains('\\') => Ok(path.to_owned()),
+    _ => Err(String::from("expected a plain string literal path")),
+  }
+}
+
+fn read_migrations(dir: &Path) -> Result<Vec<(FileVersion, PathBuf)>, String> {
+  let entries = std::fs::read_dir(dir).map_err(|err| {
+    format!(
+      "could not read migrations directory {}: {err}",
+      dir.display()
+    )
+  })?;
+
+  let mut migrations = vec![];
+
+  for entry in entries {
+    let path = entry
+      .map_err(|err| {
+        format!(
+          "could not read migrations directory {}: {err}",
+          dir.display()
+        )
+      })?
+      .path();
+
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+      continue;
+    };
+
+    if !path.is_file() || !file_name.ends_with(".sql") {
+      continue;
+    }
+
+    let version = parse_filename(file_name).ok_or_else(|| {
+      format!(
+        "malformed migration file name {}, expected v<major>_<minor>_<patch>.sql",
+        path.display()
+      )
+    })?;
+
+    migrations.push((version, path));
+  }
+
+  Ok(migrations)
+}
+
+fn check_migration(path: &Path) -> Result<(), String> {
+  let query = std::fs::read_to_string(path)
+    .map_err(|err| format!("could not read {}: {err}", path.display()))?;
+
+  check_directives(&query).map_err(|err| format!("{}: {err}", path.display()))
+}
+
+// Rejects what `PlainMigration::new` would panic on at startup.
+fn check_directives(query: &str) -> Result<(), String> {
+  let (up, _) = split_down_section(query);
+
+  match parse_directives(up) {
+    Ok(_) => Ok(()),
+    Err(line) => Err(format!("invalid migration directive: {line}")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{check_directives, rfc3339};
+
+  #[test]
+  fn check_directives_valid() {
+    let valid = [
+      "SELECT 1;",
+      "-- migrator:requires billing >= 1.2.0",
+      "  -- migrator:requires billing 1.2.0  ",
+      "-- migrator:lock-timeout 5s\n-- migrator:statement-timeout 2min",
+      "-- migrator:no-transaction\n-- migrator:lock-timeout 500ms",
+      // Only the up section is parsed.
+      "SELECT 1;\n-- down\n-- migrator:requires billing",
+    ];
+
+    for query in valid {
+      assert_eq!(check_directives(query), Ok(()), "{query}");
+    }
+  }
+
+  #[test]
+  fn check_directives_malformed() {
+    let malformed = [
+      "-- migrator:requires billing",
+      "-- migrator:requires billing >= 1.2",
+      "-- migrator:requires billing > 1.2.0",
+      "-- migrator:requires billing >= 1.2.0-rc.1",
+      "-- migrator:requires billing >= 1.2.0 extra",
+      "-- migrator:lock-timeout 5",
+      "-- migrator:lock-timeout 5 s",
+      "-- migrator:statement-timeout 1d",
+      "-- migrator:statement-timeout 18446744073709551615h",
+    ];
+
+    for query in malformed {
+      assert!(check_directives(query).is_err(), "{query}");
+    }
+  }
+
+  #[test]
+  fn rfc3339_dates() {
+    assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+    assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+    assert_eq!(rfc3339(1_714_564_805), "2024-05-01T12:00:05Z");
+  }
+}
@@ -0,0 +1,594 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand};
+use fuzion_commons::config::{DatabaseConfig, LoggingConfig, SslMode};
+use fuzion_commons::migration::lint::{lint_migration, LintLevel, LintPolicy};
+use fuzion_commons::migration::{
+  Migration, MigrationError, Migrator, MigratorTables, PlainMigration, BASE_MODULE_NAME,
+  DEFAULT_MIGRATOR_SCHEMA, DEFAULT_VERSION_TABLE,
+};
+use fuzion_commons::version::{build_info, Version};
+use itertools::Itertools as _;
+
+/// Inspect and run fuzion-commons migrations against a database.
+#[derive(Parser)]
+#[command(name = "fuzion-migrate")]
+struct Cli {
+  #[command(flatten)]
+  database: DatabaseArgs,
+
+  /// Module whose migrations are managed.
+  #[arg(long, short, global = true, default_value = BASE_MODULE_NAME)]
+  module: String,
+
+  /// Schema the module is migrated in, for modules kept per schema such as one per tenant.
+  #[arg(long, global = true)]
+  schema: Option<String>,
+
+  /// Directory of v<major>_<minor>_<patch>.sql migration files.
+  #[arg(long, global = true, env = "MIGRATIONS_DIR")]
+  migrations: Option<PathBuf>,
+
+  /// Schema snapshot applied to empty databases instead of the migrations it covers, named like
+  /// a migration file.
+  #[arg(long, global = true, env = "MIGRATIONS_BASELINE")]
+  baseline: Option<PathBuf>,
+
+  /// Schema of the migrator's bookkeeping tables.
+  #[arg(long, global = true, env = "MIGRATOR_SCHEMA", default_value = DEFAULT_MIGRATOR_SCHEMA)]
+  migrator_schema: String,
+
+  /// Name of the migrator's version table.
+  #[arg(long, global = true, env = "MIGRATOR_VERSION_TABLE", default_value = DEFAULT_VERSION_TABLE)]
+  version_table: String,
+
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Args, Default)]
+struct DatabaseArgs {
+  /// JSON file containing a `DatabaseConfig`, overridden by the flags below.
+  #[arg(long, global = true, env = "DATABASE_CONFIG")]
+  config: Option<PathBuf>,
+  #[arg(long, global = true, env = "DATABASE_HOST")]
+  host: Option<String>,
+  #[arg(long, global = true, env = "DATABASE_PORT")]
+  port: Option<u16>,
+  #[arg(long, global = true, env = "DATABASE_USER")]
+  user: Option<String>,
+  #[arg(long, global = true, env = "DATABASE_PASSWORD", hide_env_values = true)]
+  password: Option<String>,
+  #[arg(long, global = true, env = "DATABASE_NAME")]
+  name: Option<String>,
+  /// disable, prefer, require, verify-ca or verify-full.
+  #[arg(long, global = true, env = "DATABASE_SSL_MODE")]
+  ssl_mode: Option<SslMode>,
+  #[arg(long, global = true, env = "DATABASE_SSL_ROOT_CERT")]
+  ssl_root_cert: Option<String>,
+  #[arg(long, global = true, env = "DATABASE_SSL_CERT")]
+  ssl_cert: Option<String>,
+  #[arg(long, global = true, env = "DATABASE_SSL_KEY")]
+  ssl_key: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  #[command(flatten)]
+  Database(DatabaseCommand),
+  /// Check migration SQL for statements that take heavy locks or rewrite tables.
+  Lint {
+    /// Fail on any finding instead of only reporting it.
+    #[arg(long)]
+    deny: bool,
+  },
+}
+
+#[derive(Subcommand)]
+enum DatabaseCommand {
+  /// Show the current version, dirty state and pending migrations.
+  Status,
+  /// Apply pending migrations, optionally only up to a version.
+  Up {
+    #[arg(long)]
+    to: Option<Version>,
+  },
+  /// Revert migrations down to a version.
+  Down {
+    #[arg(long)]
+    to: Version,
+  },
+  /// List pending migrations without applying them.
+  Plan {
+    /// Print a reviewable SQL script instead of a summary.
+    #[arg(long)]
+    sql: bool,
+  },
+  /// Show the migration history of the module.
+  History,
+  /// Check applied migrations against their recorded checksums.
+  Verify,
+}
+
+impl DatabaseArgs {
+  fn to_config(&self) -> Result<DatabaseConfig, String> {
+    let mut config = match &self.config {
+      Some(path) => {
+        let content =
+          std::fs::read(path).map_err(|err| format!("could not read {}: {err}", path.display()))?;
+
+        serde_json::from_slice(&content)
+          .map_err(|err| format!("could not parse {}: {err}", path.display()))?
+      }
+      None => DatabaseConfig::default(),
+    };
+
+    if let Some(host) = &self.host {
+      config.host = host.to_owned();
+    }
+    if let Some(port) = self.port {
+      config.port = port;
+    }
+    if let Some(user) = &self.user {
+      config.user = user.to_owned();
+    }
+    if let Some(password) = &self.password {
+      config.password = password.to_owned();
+    }
+    if let Some(name) = &self.name {
+      config.name = name.to_owned();
+    }
+    if let Some(ssl_mode) = self.ssl_mode {
+      config.ssl_mode = ssl_mode;
+    }
+    if let Some(ssl_root_cert) = &self.ssl_root_cert {
+      config.ssl_root_cert = Some(ssl_root_cert.to_owned());
+    }
+    if let Some(ssl_cert) = &self.ssl_cert {
+      config.ssl_cert = Some(ssl_cert.to_owned());
+    }
+    if let Some(ssl_key) = &self.ssl_key {
+      config.ssl_key = Some(ssl_key.to_owned());
+    }
+
+    Ok(config)
+  }
+}
+
+/// Loads a single migration file. The SQL lives for the rest of the process, so it is leaked to
+/// satisfy `PlainMigration`'s `'static` bound.
+fn load_migration(path: &Path) -> Result<PlainMigration, String> {
+  let source: &'static str = Box::leak(path.display().to_string().into_boxed_str());
+  let version = path
+    .file_name()
+    .and_then(|name| name.to_str())
+    .and_then(Version::from_filename)
+    .ok_or_else(|| {
+      format!("malformed migration file name {source}, expected v<major>_<minor>_<patch>.sql")
+    })?;
+  let query =
+    std::fs::read_to_string(path).map_err(|err| format!("could not read {source}: {err}"))?;
+
+  let migration = PlainMigration::try_new(version, Box::leak(query.into_boxed_str()))
+    .map_err(|err| format!("{source}: {err}"))?;
+
+  Ok(migration.with_source(source))
+}
+
+fn load_migrations(dir: &Path) -> Result<Vec<Box<dyn Migration>>, String> {
+  let entries =
+    std::fs::read_dir(dir).map_err(|err| format!("could not read {}: {err}", dir.display()))?;
+
+  let mut migrations = vec![];
+
+  for entry in entries {
+    let path = entry
+      .map_err(|err| format!("could not read {}: {err}", dir.display()))?
+      .path();
+
+    if path.extension().and_then(|ext| ext.to_str()) != Some("sql") {
+      continue;
+    }
+
+    let migration = load_migration(&path)?;
+
+    migrations.push((migration.version(), migration));
+  }
+
+  migrations.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+  for pair in migrations.windows(2) {
+    if pair[0].0 == pair[1].0 {
+      return Err(format!(
+        "duplicate migration version {}: {} and {}",
+        pair[0].0,
+        pair[0].1.source().unwrap_or_default(),
+        pair[1].1.source().unwrap_or_default(),
+      ));
+    }
+  }
+
+  Ok(
+    migrations
+      .into_iter()
+      .map(|(_, migration)| Box::new(migration) as Box<dyn Migration>)
+      .collect(),
+  )
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+  let cli = Cli::parse();
+
+  fuzion_commons::logging::init(&LoggingConfig::default());
+  fuzion_commons::logging::log_build_info(&build_info!());
+
+  match run(cli).await {
+    Ok(code) => code,
+    Err(err) => {
+      eprintln!("error: {err}");
+
+      ExitCode::FAILURE
+    }
+  }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode, String> {
+  let migrations = match (&cli.migrations, &cli.command) {
+    (Some(dir), _) => load_migrations(dir)?,
+    (None, Command::Database(DatabaseCommand::Status | DatabaseCommand::History)) => vec![],
+    (None, _) => return Err(String::from("--migrations is required for this command")),
+  };
+
+  match &cli.command {
+    Command::Database(command) => run_database(&cli, command, migrations).await,
+    Command::Lint { deny } => Ok(run_lint(&migrations, *deny)),
+  }
+}
+
+/// Linting only reads the files, so it needs no database.
+fn run_lint(migrations: &[Box<dyn Migration>], deny: bool) -> ExitCode {
+  let policy = match deny {
+    true => LintPolicy::new(LintLevel::Deny),
+    false => LintPolicy::default(),
+  };
+  let findings: Vec<_> = migrations
+    .iter()
+    .flat_map(|migration| lint_migration(&**migration, &policy))
+    .collect();
+
+  for finding in &findings {
+    println!("{finding}");
+  }
+
+  match findings
+    .iter()
+    .any(|finding| finding.level == LintLevel::Deny)
+  {
+    true => ExitCode::FAILURE,
+    false => ExitCode::SUCCESS,
+  }
+}
+
+async fn run_database(
+  cli: &Cli,
+  command: &DatabaseCommand,
+  migrations: Vec<Box<dyn Migration>>,
+) -> Result<ExitCode, String> {
+  let config = cli.database.to_config()?;
+  let pool = config.get_db_pool().await.map_err(|err| err.to_string())?;
+  let client = pool.get().await.map_err(|err| err.to_string())?;
+
+  let mut migrator = Migrator::new(&cli.module, client, migrations).with_tables(MigratorTables {
+    schema: cli.migrator_schema.clone(),
+    version_table: cli.version_table.clone(),
+  });
+
+  if let Some(schema) = &cli.schema {
+    migrator = migrator.with_schema(schema);
+  }
+
+  if let Some(path) = &cli.baseline {
+    migrator = migrator.with_baseline(Box::new(load_migration(path)?));
+  }
+
+  match command {
+    DatabaseCommand::Status => {
+      let plan = migrator.plan().await.map_err(describe)?;
+
+      println!("module:  {}", cli.module);
+
+      if let Some(schema) = &cli.schema {
+        println!("schema:  {schema}");
+      }
+
+      println!("version: {}", plan.current);
+
+      if let Some(baseline) = migrator.get_baseline().await.map_err(describe)? {
+        println!("baseline: {baseline}");
+      }
+
+      match migrator.dirty().await.map_err(describe)? {
+        Some(dirty) => println!(
+          "dirty:   {} ({}): {}",
+          dirty.version,
+          dirty.direction.as_str(),
+          dirty.error.as_deref().unwrap_or("interrupted")
+        ),
+        None => println!("dirty:   no"),
+      }
+
+      if cli.migrations.is_some() {
+        println!("pending: {}", plan.migrations.len());
+      }
+    }
+    DatabaseCommand::Up { to: Some(to) } => {
+      let version = migrator.get_version().await.map_err(describe)?;
+
+      if *to < version {
+        return Err(format!(
+          "{to} is older than the current version {version}, use down to revert"
+        ));
+      }
+
+      migrator.migrate_to(to.clone()).await.map_err(describe)?;
+    }
+    DatabaseCommand::Up { to: None } => migrator.migrate().await.map_err(describe)?,
+    DatabaseCommand::Down { to } => {
+      let version = migrator.get_version().await.map_err(describe)?;
+
+      if *to > version {
+        return Err(format!("{to} is newer than the current version {version}"));
+      }
+
+      migrator.migrate_to(to.clone()).await.map_err(describe)?;
+    }
+    DatabaseCommand::Plan { sql: true } => {
+      let plan = migrator.plan().await.map_err(describe)?;
+
+      print!("{}", plan.to_sql_script().map_err(describe)?);
+    }
+    DatabaseCommand::Plan { sql: false } => {
+      let plan = migrator.plan().await.map_err(describe)?;
+
+      println!("{} is at {}", cli.module, plan.current);
+
+      for migration in &plan.migrations {
+        println!(
+          "  {} {}{}",
+          migration.version,
+          migration.source.as_deref().unwrap_or("<custom>"),
+          match (migration.baseline, migration.transactional) {
+            (true, _) => " (baseline)",
+            (false, true) => "",
+            (false, false) => " (no transaction)",
+          }
+        );
+      }
+
+      if plan.is_empty() {
+        println!("  nothing to do");
+      }
+    }
+    DatabaseCommand::History => {
+      for record in migrator.history().await.map_err(describe)? {
+        let applied_at = record
+          .applied_at
+          .duration_since(std::time::UNIX_EPOCH)
+          .map(|since| since.as_secs())
+          .unwrap_or_default();
+
+        println!(
+          "{applied_at} {} {} {} {:?} {} {}",
+          record.version,
+          record.direction.as_str(),
+          match record.success {
+            true => "ok",
+            false => "failed",
+          },
+          record.duration,
+          record.host.as_deref().unwrap_or("-"),
+          record.error.as_deref().unwrap_or(""),
+        );
+      }
+    }
+    DatabaseCommand::Verify => match migrator.verify().await {
+      Ok(unrecorded) if unrecorded.is_empty() => {
+        println!("{}: all applied migrations match", cli.module)
+      }
+      Ok(unrecorded) => println!(
+        "{}: applied migrations match, no checksum recorded yet for {}",
+        cli.module,
+        unrecorded.iter().join(", ")
+      ),
+      Err(MigrationError::ChecksumMismatch(versions)) => {
+        eprintln!(
+          "{}: applied migrations have changed: {}",
+          cli.module,
+          versions.iter().join(", ")
+        );
+
+        return Ok(ExitCode::FAILURE);
+      }
+      Err(err) => return Err(describe(err)),
+    },
+  }
+
+  Ok(ExitCode::SUCCESS)
+}
+
+fn describe(err: MigrationError) -> String {
+  match err {
+    MigrationError::Postgres(err) => fuzion_commons::db::fmt_pg_error(&err),
+    err => err.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use clap::Parser;
+  use fuzion_commons::config::SslMode;
+  use fuzion_commons::version::Version;
+
+  use super::{load_migrations, Cli, Command, DatabaseArgs, DatabaseCommand};
+
+  fn temp_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fuzion-migrate-{}-{name}", std::process::id()));
+
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for (file, content) in files {
+      std::fs::write(dir.join(file), content).unwrap();
+    }
+
+    dir
+  }
+
+  #[test]
+  fn load_migrations_sorted() {
+    let dir = temp_dir(
+      "sorted",
+      &[
+        ("v0_10_0.sql", "SELECT 3;"),
+        ("v0_2_0.sql", "SELECT 2;"),
+        ("v0_1_0.sql", "SELECT 1;\n-- down\nSELECT 0;"),
+        ("README.md", "not a migration"),
+      ],
+    );
+
+    let migrations = load_migrations(&dir).unwrap();
+    let loaded: Vec<_> = migrations
+      .iter()
+      .map(|migration| (migration.version(), migration.sql().unwrap()))
+      .collect();
+
+    assert_eq!(
+      loaded,
+      [
+        (Version::new(0, 1, 0), "SELECT 1;\n"),
+        (Version::new(0, 2, 0), "SELECT 2;"),
+        (Version::new(0, 10, 0), "SELECT 3;"),
+      ]
+    );
+    assert!(migrations[0].is_reversible());
+    assert_eq!(
+      migrations[2].source(),
+      Some(dir.join("v0_10_0.sql").display().to_string().as_str())
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn load_migrations_invalid() {
+    let cases = [
+      (
+        "name",
+        vec![("v1_0.sql", "SELECT 1;")],
+        "malformed migration file name",
+      ),
+      (
+        "duplicate",
+        vec![("v1_0_0.sql", "SELECT 1;"), ("v01_0_0.sql", "SELECT 1;")],
+        "duplicate migration version",
+      ),
+      (
+        "directive",
+        vec![("v1_0_0.sql", "-- migrator:lock-timeout 5\nSELECT 1;")],
+        "-- migrator:lock-timeout 5",
+      ),
+    ];
+
+    for (name, files, error) in cases {
+      let dir = temp_dir(name, &files);
+
+      match load_migrations(&dir) {
+        Ok(_) => panic!("{name}: loaded"),
+        Err(err) => assert!(err.contains(error), "{name}: {err}"),
+      }
+
+      std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    assert!(load_migrations(&std::env::temp_dir().join("fuzion-migrate-missing")).is_err());
+  }
+
+  #[test]
+  fn database_args_override_config() {
+    let dir = temp_dir(
+      "config",
+      &[
+        (
+          "database.json",
+          r#"{ "host": "db", "port": 6432, "user": "app", "password": "", "name": "app", "ssl_mode": "require" }"#,
+        ),
+        ("invalid.json", "{ \"port\": \"db\" }"),
+      ],
+    );
+
+    let config = DatabaseArgs::default().to_config().unwrap();
+    assert_eq!((config.host.as_str(), config.port), ("localhost", 5432));
+
+    let config = DatabaseArgs {
+      config: Some(dir.join("database.json")),
+      ..Default::default()
+    }
+    .to_config()
+    .unwrap();
+    assert_eq!(
+      (config.host.as_str(), config.port, config.user.as_str()),
+      ("db", 6432, "app")
+    );
+    assert_eq!(config.ssl_mode, SslMode::Require);
+
+    let config = DatabaseArgs {
+      config: Some(dir.join("database.json")),
+      host: Some(String::from("replica")),
+      password: Some(String::from("secret")),
+      ssl_mode: Some(SslMode::VerifyFull),
+      ssl_root_cert: Some(String::from("ca.pem")),
+      ..Default::default()
+    }
+    .to_config()
+    .unwrap();
+    assert_eq!(
+      (config.host.as_str(), config.port, config.password.as_str()),
+      ("replica", 6432, "secret")
+    );
+    assert_eq!(config.ssl_mode, SslMode::VerifyFull);
+    assert_eq!(config.ssl_root_cert.as_deref(), Some("ca.pem"));
+
+    for file in ["invalid.json", "missing.json"] {
+      let err = DatabaseArgs {
+        config: Some(dir.join(file)),
+        ..Default::default()
+      }
+      .to_config()
+      .map(|_| ())
+      .unwrap_err();
+
+      assert!(err.contains(file), "{err}");
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn parse_commands() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["fuzion-migrate"], args].concat()).unwrap();
+
+    assert!(matches!(
+      parse(&["up", "--to", "1.2.0"]).command,
+      Command::Database(DatabaseCommand::Up { to: Some(to) }) if to == Version::new(1, 2, 0)
+    ));
+    assert!(matches!(
+      parse(&["lint", "--deny"]).command,
+      Command::Lint { deny: true }
+    ));
+    assert!(Cli::try_parse_from(["fuzion-migrate", "down"]).is_err());
+  }
+}
@@ -1,14 +1,21 @@
+use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 
 use actix_web::http::Uri;
 use actix_web::rt::time::sleep;
 use deadpool::managed::BuildError;
-use deadpool_postgres::{Manager, ManagerConfig, Pool as Deadpool, RecyclingMethod};
+use deadpool_postgres::{Manager, ManagerConfig, Pool as Deadpool, RecyclingMethod, Runtime};
 use smart_default::SmartDefault;
 use thiserror::Error;
 
 use crate::db::PgPool;
-use crate::serde::{default_true, deserialize_log_level, serialize_log_level};
+use crate::serde::{
+  ceil_millis, default_true, deserialize_log_level, deserialize_optional_duration,
+  serialize_log_level, serialize_optional_duration,
+};
+
+mod tls;
 
 pub fn clap_arg_to_log_level(level: &str) -> Result<slog::Level, String> {
   match level {
@@ -48,6 +55,140 @@ pub struct DatabaseConfig {
   pub password: String,
   #[default = "fuzion-veritas"]
   pub name: String,
+  #[serde(default)]
+  pub ssl_mode: SslMode,
+  #[serde(default)]
+  pub ssl_root_cert: Option<String>,
+  #[serde(default)]
+  pub ssl_cert: Option<String>,
+  #[serde(default)]
+  pub ssl_key: Option<String>,
+  #[serde(default)]
+  pub max_size: Option<usize>,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub wait_timeout: Option<Duration>,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub create_timeout: Option<Duration>,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub recycle_timeout: Option<Duration>,
+  #[serde(default)]
+  pub recycling_method: PoolRecycling,
+  #[serde(default)]
+  pub application_name: Option<String>,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub connect_timeout: Option<Duration>,
+  #[default = true]
+  #[serde(default = "default_true")]
+  pub keepalives: bool,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub keepalives_idle: Option<Duration>,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub keepalives_interval: Option<Duration>,
+  #[serde(default)]
+  pub keepalives_retries: Option<u32>,
+  /// Default `statement_timeout` of every session. Migrations without their own statement
+  /// timeout run under it as well.
+  #[serde(
+    default,
+    deserialize_with = "deserialize_optional_duration",
+    serialize_with = "serialize_optional_duration"
+  )]
+  pub statement_timeout: Option<Duration>,
+  #[serde(default)]
+  pub search_path: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolRecycling {
+  #[default]
+  Fast,
+  Verified,
+  /// Also resets the session state, such as settings, temporary tables and advisory locks.
+  Clean,
+}
+
+impl From<PoolRecycling> for RecyclingMethod {
+  fn from(value: PoolRecycling) -> Self {
+    match value {
+      PoolRecycling::Fast => RecyclingMethod::Fast,
+      PoolRecycling::Verified => RecyclingMethod::Verified,
+      PoolRecycling::Clean => RecyclingMethod::Clean,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+  #[default]
+  Disable,
+  Prefer,
+  /// TLS without verifying the server, or as `VerifyCa` when `ssl_root_cert` is set.
+  Require,
+  VerifyCa,
+  VerifyFull,
+}
+
+impl SslMode {
+  pub const ALL: [SslMode; 5] = [
+    SslMode::Disable,
+    SslMode::Prefer,
+    SslMode::Require,
+    SslMode::VerifyCa,
+    SslMode::VerifyFull,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      SslMode::Disable => "disable",
+      SslMode::Prefer => "prefer",
+      SslMode::Require => "require",
+      SslMode::VerifyCa => "verify-ca",
+      SslMode::VerifyFull => "verify-full",
+    }
+  }
+}
+
+impl FromStr for SslMode {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    SslMode::ALL
+      .into_iter()
+      .find(|mode| mode.name() == value)
+      .ok_or_else(|| format!("unknown ssl mode {value}"))
+  }
+}
+
+impl fmt::Display for SslMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
 }
 
 impl DatabaseConfig {
@@ -58,16 +199,87 @@ impl DatabaseConfig {
     pg_config.dbname(&self.name);
     pg_config.host(&self.host);
     pg_config.port(self.port);
+    pg_config.keepalives(self.keepalives);
 
-    let manager = Manager::from_config(
-      pg_config,
-      tokio_postgres::NoTls,
-      ManagerConfig {
-        recycling_method: RecyclingMethod::Fast,
-      },
-    );
+    if let Some(application_name) = &self.application_name {
+      pg_config.application_name(application_name);
+    }
+    if let Some(connect_timeout) = self.connect_timeout {
+      pg_config.connect_timeout(connect_timeout);
+    }
+    if let Some(keepalives_idle) = self.keepalives_idle {
+      pg_config.keepalives_idle(keepalives_idle);
+    }
+    if let Some(keepalives_interval) = self.keepalives_interval {
+      pg_config.keepalives_interval(keepalives_interval);
+    }
+    if let Some(keepalives_retries) = self.keepalives_retries {
+      pg_config.keepalives_retries(keepalives_retries);
+    }
+
+    let options = self.session_options();
+
+    if !options.is_empty() {
+      pg_config.options(&options);
+    }
+
+    let manager_config = ManagerConfig {
+      recycling_method: self.recycling_method.into(),
+    };
 
-    Ok(Deadpool::builder(manager).build()?.into())
+    let manager = match self.ssl_mode {
+      // Certificates are only configured to be used, silently connecting without TLS would hide
+      // a mistyped ssl_mode.
+      SslMode::Disable
+        if self.ssl_root_cert.is_some() || self.ssl_cert.is_some() || self.ssl_key.is_some() =>
+      {
+        return Err(DatabaseConfigError::Tls(String::from(
+          "ssl_root_cert, ssl_cert and ssl_key require an ssl_mode other than disable",
+        )));
+      }
+      SslMode::Disable => Manager::from_config(pg_config, tokio_postgres::NoTls, manager_config),
+      SslMode::Prefer => {
+        pg_config.ssl_mode(tokio_postgres::config::SslMode::Prefer);
+
+        Manager::from_config(pg_config, tls::make_tls_connect(self)?, manager_config)
+      }
+      _ => {
+        pg_config.ssl_mode(tokio_postgres::config::SslMode::Require);
+
+        Manager::from_config(pg_config, tls::make_tls_connect(self)?, manager_config)
+      }
+    };
+
+    let mut builder = Deadpool::builder(manager)
+      .runtime(Runtime::Tokio1)
+      .wait_timeout(self.wait_timeout)
+      .create_timeout(self.create_timeout)
+      .recycle_timeout(self.recycle_timeout);
+
+    if let Some(max_size) = self.max_size {
+      builder = builder.max_size(max_size);
+    }
+
+    Ok(builder.build()?.into())
+  }
+
+  fn session_options(&self) -> String {
+    // Spaces separate options, so they are escaped within values.
+    let escape = |value: &str| value.replace('\\', "\\\\").replace(' ', "\\ ");
+
+    let mut options = vec![];
+
+    if let Some(statement_timeout) = self.statement_timeout {
+      options.push(format!(
+        "-c statement_timeout={}",
+        ceil_millis(statement_timeout)
+      ));
+    }
+    if let Some(search_path) = &self.search_path {
+      options.push(format!("-c search_path={}", escape(search_path)));
+    }
+
+    options.join(" ")
   }
 
   pub async fn test_db_connection(
@@ -106,6 +318,8 @@ pub enum DatabaseConfigError {
   DeadpoolBuildError(#[from] BuildError),
   #[error("Init timeout")]
   InitTimeout,
+  #[error("Invalid database TLS configuration: {0}")]
+  Tls(String),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, SmartDefault)]
@@ -212,3 +426,204 @@ impl HttpConfigWithPublicPrivate {
 pub struct HttpEndpointConfig {
   pub endpoint: String,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ssl_mode_names() {
+    for mode in SslMode::ALL {
+      assert_eq!(mode.to_string().parse::<SslMode>(), Ok(mode));
+      assert_eq!(
+        serde_json::to_string(&mode).unwrap(),
+        format!("\"{}\"", mode.name())
+      );
+    }
+
+    assert_eq!("verify-ca".parse::<SslMode>(), Ok(SslMode::VerifyCa));
+    assert_eq!(SslMode::VerifyFull.to_string(), "verify-full");
+    assert!("verify_full".parse::<SslMode>().is_err());
+    assert!("Require".parse::<SslMode>().is_err());
+    assert!("".parse::<SslMode>().is_err());
+  }
+
+  async fn tls_error(config: DatabaseConfig) -> String {
+    match config.get_db_pool().await {
+      Err(DatabaseConfigError::Tls(message)) => message,
+      result => panic!("expected a TLS error, got {:?}", result.err()),
+    }
+  }
+
+  #[tokio::test]
+  async fn client_certificate_needs_key() {
+    let config = |ssl_cert: Option<&str>, ssl_key: Option<&str>| DatabaseConfig {
+      ssl_mode: SslMode::Require,
+      ssl_cert: ssl_cert.map(String::from),
+      ssl_key: ssl_key.map(String::from),
+      ..Default::default()
+    };
+
+    for config in [
+      config(Some("client.crt"), None),
+      config(None, Some("client.key")),
+    ] {
+      assert_eq!(
+        tls_error(config).await,
+        "ssl_cert and ssl_key must be set together"
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn certificates_need_tls() {
+    let path = || Some(String::from("server.crt"));
+
+    for config in [
+      DatabaseConfig {
+        ssl_root_cert: path(),
+        ..Default::default()
+      },
+      DatabaseConfig {
+        ssl_cert: path(),
+        ssl_key: path(),
+        ..Default::default()
+      },
+      DatabaseConfig {
+        ssl_key: path(),
+        ..Default::default()
+      },
+    ] {
+      assert_eq!(
+        tls_error(config).await,
+        "ssl_root_cert, ssl_cert and ssl_key require an ssl_mode other than disable"
+      );
+    }
+
+    assert!(DatabaseConfig::default().get_db_pool().await.is_ok());
+  }
+
+  #[test]
+  fn session_options() {
+    let config = |statement_timeout: Option<Duration>, search_path: Option<&str>| DatabaseConfig {
+      statement_timeout,
+      search_path: search_path.map(String::from),
+      ..Default::default()
+    };
+
+    let cases = [
+      (config(None, None), ""),
+      (
+        config(Some(Duration::from_secs(5)), None),
+        "-c statement_timeout=5000",
+      ),
+      (
+        config(Some(Duration::from_micros(1)), None),
+        "-c statement_timeout=1",
+      ),
+      (
+        config(Some(Duration::from_millis(250)), Some("app, public")),
+        "-c statement_timeout=250 -c search_path=app,\\ public",
+      ),
+      (
+        config(None, Some(r#""my app", "back\slash""#)),
+        r#"-c search_path="my\ app",\ "back\\slash""#,
+      ),
+    ];
+
+    for (config, options) in cases {
+      assert_eq!(config.session_options(), options);
+    }
+  }
+
+  #[test]
+  fn database_config_defaults() {
+    let config: DatabaseConfig = serde_json::from_str(
+      r#"{"host": "db", "port": 5433, "user": "app", "password": "secret", "name": "app"}"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.ssl_mode, SslMode::Disable);
+    assert_eq!(config.ssl_root_cert, None);
+    assert_eq!(config.max_size, None);
+    assert_eq!(config.wait_timeout, None);
+    assert_eq!(config.recycling_method, PoolRecycling::Fast);
+    assert_eq!(config.application_name, None);
+    assert!(config.keepalives);
+    assert_eq!(config.keepalives_retries, None);
+    assert_eq!(config.statement_timeout, None);
+    assert_eq!(config.search_path, None);
+
+    let default = DatabaseConfig::default();
+
+    assert_eq!(default.ssl_mode, SslMode::Disable);
+    assert!(default.keepalives);
+  }
+
+  #[test]
+  fn database_config_every_field() {
+    let json = r#"{
+      "host": "db",
+      "port": 5433,
+      "user": "app",
+      "password": "secret",
+      "name": "app",
+      "ssl_mode": "verify-full",
+      "ssl_root_cert": "/etc/ssl/root.crt",
+      "ssl_cert": "/etc/ssl/client.crt",
+      "ssl_key": "/etc/ssl/client.key",
+      "max_size": 32,
+      "wait_timeout": "5s",
+      "create_timeout": "1500ms",
+      "recycle_timeout": "2min",
+      "recycling_method": "clean",
+      "application_name": "billing",
+      "connect_timeout": "10s",
+      "keepalives": false,
+      "keepalives_idle": "1h",
+      "keepalives_interval": "30s",
+      "keepalives_retries": 3,
+      "statement_timeout": "0s",
+      "search_path": "app, public"
+    }"#;
+
+    let config: DatabaseConfig = serde_json::from_str(json).unwrap();
+
+    assert_eq!(config.ssl_mode, SslMode::VerifyFull);
+    assert_eq!(config.ssl_root_cert.as_deref(), Some("/etc/ssl/root.crt"));
+    assert_eq!(config.ssl_cert.as_deref(), Some("/etc/ssl/client.crt"));
+    assert_eq!(config.ssl_key.as_deref(), Some("/etc/ssl/client.key"));
+    assert_eq!(config.max_size, Some(32));
+    assert_eq!(config.wait_timeout, Some(Duration::from_secs(5)));
+    assert_eq!(config.create_timeout, Some(Duration::from_millis(1500)));
+    assert_eq!(config.recycle_timeout, Some(Duration::from_secs(120)));
+    assert_eq!(config.recycling_method, PoolRecycling::Clean);
+    assert_eq!(config.application_name.as_deref(), Some("billing"));
+    assert_eq!(config.connect_timeout, Some(Duration::from_secs(10)));
+    assert!(!config.keepalives);
+    assert_eq!(config.keepalives_idle, Some(Duration::from_secs(3600)));
+    assert_eq!(config.keepalives_interval, Some(Duration::from_secs(30)));
+    assert_eq!(config.keepalives_retries, Some(3));
+    assert_eq!(config.statement_timeout, Some(Duration::ZERO));
+    assert_eq!(config.search_path.as_deref(), Some("app, public"));
+
+    // Serializing writes the same values back.
+    let value: serde_json::Value = serde_json::from_str(json).unwrap();
+
+    assert_eq!(serde_json::to_value(&config).unwrap(), value);
+  }
+
+  #[test]
+  fn database_config_invalid_values() {
+    for json in [
+      r#"{"host": "db", "port": 5432, "user": "", "password": "", "name": "", "ssl_mode": "on"}"#,
+      r#"{"host": "db", "port": 5432, "user": "", "password": "", "name": "", "wait_timeout": "5"}"#,
+      r#"{"host": "db", "port": 5432, "user": "", "password": "", "name": "", "recycling_method": "Fast"}"#,
+    ] {
+      assert!(
+        serde_json::from_str::<DatabaseConfig>(json).is_err(),
+        "{json}"
+      );
+    }
+  }
+}
@@ -0,0 +1,174 @@
+//! rustls setup for `DatabaseConfig::get_db_pool`, following libpq's `sslmode` semantics.
+
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+  CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use super::{DatabaseConfig, DatabaseConfigError, SslMode};
+
+pub(super) fn make_tls_connect(
+  config: &DatabaseConfig,
+) -> Result<MakeRustlsConnect, DatabaseConfigError> {
+  let builder = ClientConfig::builder();
+  let provider = builder.crypto_provider().clone();
+
+  let builder = match (config.ssl_mode, &config.ssl_root_cert) {
+    (SslMode::VerifyFull, _) => builder.with_root_certificates(load_roots(config)?),
+    // Like libpq, `require` verifies the CA when one is configured.
+    (SslMode::VerifyCa, _) | (SslMode::Require, Some(_)) => {
+      let verifier =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(load_roots(config)?), provider)
+          .build()
+          .map_err(tls_error)?;
+
+      builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(CaOnlyVerifier(verifier)))
+    }
+    _ => builder
+      .dangerous()
+      .with_custom_certificate_verifier(Arc::new(UnverifiedServer(
+        provider.signature_verification_algorithms,
+      ))),
+  };
+
+  let tls_config = match (&config.ssl_cert, &config.ssl_key) {
+    (Some(cert), Some(key)) => {
+      let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|err| DatabaseConfigError::Tls(format!("{key}: {err}")))?;
+
+      builder
+        .with_client_auth_cert(load_certs(cert)?, key)
+        .map_err(tls_error)?
+    }
+    (None, None) => builder.with_no_client_auth(),
+    _ => {
+      return Err(DatabaseConfigError::Tls(String::from(
+        "ssl_cert and ssl_key must be set together",
+      )))
+    }
+  };
+
+  Ok(MakeRustlsConnect::new(tls_config))
+}
+
+fn tls_error(err: impl ToString) -> DatabaseConfigError {
+  DatabaseConfigError::Tls(err.to_string())
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, DatabaseConfigError> {
+  CertificateDer::pem_file_iter(path)
+    .and_then(|certs| certs.collect())
+    .map_err(|err| DatabaseConfigError::Tls(format!("{path}: {err}")))
+}
+
+/// The CA certificates of `ssl_root_cert`, or the platform's when it is not set.
+fn load_roots(config: &DatabaseConfig) -> Result<RootCertStore, DatabaseConfigError> {
+  let certs = match &config.ssl_root_cert {
+    Some(path) => load_certs(path)?,
+    None => rustls_native_certs::load_native_certs().certs,
+  };
+
+  let mut roots = RootCertStore::empty();
+
+  match roots.add_parsable_certificates(certs) {
+    (0, _) => Err(DatabaseConfigError::Tls(String::from(
+      "no usable CA certificates to verify the server with",
+    ))),
+    _ => Ok(roots),
+  }
+}
+
+/// Encrypts without authenticating the server, as libpq's `prefer` and `require` do.
+#[derive(Debug)]
+struct UnverifiedServer(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for UnverifiedServer {
+  fn verify_server_cert(
+    &self,
+    _end_entity: &CertificateDer<'_>,
+    _intermediates: &[CertificateDer<'_>],
+    _server_name: &ServerName<'_>,
+    _ocsp_response: &[u8],
+    _now: UnixTime,
+  ) -> Result<ServerCertVerified, rustls::Error> {
+    Ok(ServerCertVerified::assertion())
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls12_signature(message, cert, dss, &self.0)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls13_signature(message, cert, dss, &self.0)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.0.supported_schemes()
+  }
+}
+
+/// Verifies the certificate chain but not the host name, as libpq's `verify-ca` does.
+#[derive(Debug)]
+struct CaOnlyVerifier(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for CaOnlyVerifier {
+  fn verify_server_cert(
+    &self,
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    server_name: &ServerName<'_>,
+    ocsp_response: &[u8],
+    now: UnixTime,
+  ) -> Result<ServerCertVerified, rustls::Error> {
+    match self
+      .0
+      .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    {
+      Err(rustls::Error::InvalidCertificate(
+        CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+      )) => Ok(ServerCertVerified::assertion()),
+      result => result,
+    }
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    self.0.verify_tls12_signature(message, cert, dss)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    self.0.verify_tls13_signature(message, cert, dss)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.0.supported_verify_schemes()
+  }
+}
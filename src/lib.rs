@@ -20,3 +20,5 @@ pub mod response;
 pub mod serde;
 pub mod uri;
 pub mod version;
+
+pub use fuzion_commons_macros::plain_migration;
@@ -8,6 +8,7 @@ use lazy_static::lazy_static;
 use slog::Drain;
 
 use crate::config::LoggingConfig;
+use crate::version::BuildInfo;
 
 lazy_static! {
   static ref LOG_GUARD: Arc<Mutex<Option<LoggingGuard>>> = Arc::new(Mutex::new(None));
@@ -127,3 +128,9 @@ pub fn init(config: &LoggingConfig) {
     *log_guard = Some(LoggingGuard { _scope_guard });
   }
 }
+
+/// Logs what the running binary was built from. `init` cannot do it, as only the binary's own
+/// crate can capture its `build_info!`, so services call this right after `init`.
+pub fn log_build_info(build_info: &BuildInfo) {
+  info!("Starting {build_info}");
+}
@@ -1,339 +1,3844 @@
+// `MigrationError` carries several versions, and it is only returned on the slow path of a
+// migration run.
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut, RangeInclusive};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
 use async_trait::async_trait;
-use lazy_static::lazy_static;
-use regex::Regex;
+use fuzion_commons_directives::{
+  has_no_transaction_directive, parse_directives, parse_filename, split_down_section, Directive,
+};
+use itertools::Itertools as _;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tokio::time::sleep;
+use tokio_postgres::error::SqlState;
 
 use crate::config::DatabaseConfigError;
-use crate::db::DeadpoolPoolError;
-use crate::version::Version;
+use crate::db::{fmt_pg_error, DeadpoolPoolError};
+use crate::version::{ModuleVersion, Version};
+
+pub use self::coordinator::MigrationCoordinator;
+use self::lint::{lint_migration, LintFinding, LintLevel, LintPolicy};
+pub use self::schemas::{SchemaMigrationSummary, SchemaMigrator, SchemaOutcome, SchemaResult};
+use self::seed::SeedKind;
+pub use self::seed::{Environment, Seed, SeedFn, SeedSet};
+use self::sql::{identifier, split_statements, tokenize};
+
+mod coordinator;
+pub mod lint;
+pub mod online;
+mod schemas;
+mod seed;
+mod sql;
+pub mod testing;
 
 pub struct Migrator<'a> {
-  module_name: String,
+  module: ModuleKey,
   migrations: Vec<Box<dyn Migration + 'a>>,
-  db_client: deadpool_postgres::Client,
+  baseline: Option<Box<dyn Migration + 'a>>,
+  repeatables: Vec<RepeatableMigration>,
+  seeds: Vec<SeedSet>,
+  environment: Option<Environment>,
+  tables: MigratorTables,
+  lint_policy: LintPolicy,
+  db_client: MigratorClient,
+  lock_timeout: Option<Duration>,
+  timeouts: MigrationTimeouts,
+  lock_retries: u32,
+  lock_retry_backoff: Duration,
 }
 
-lazy_static! {
-  static ref MIGRATION_FILE_VERSION: Regex = Regex::new("/v(\\d+)_(\\d+)_(\\d+)+.sql$").unwrap();
+/// A module's rows in the bookkeeping tables, which are kept apart per schema the module is
+/// migrated in.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct ModuleKey {
+  name: String,
+  schema: Option<String>,
 }
 
-impl Version {
-  pub fn from_filename(filename: &str) -> Option<Self> {
-    let captures = MIGRATION_FILE_VERSION.captures(filename).unwrap();
+impl ModuleKey {
+  /// The `schema` column, empty for modules migrated without `with_schema`.
+  fn schema(&self) -> &str {
+    self.schema.as_deref().unwrap_or("")
+  }
 
-    Some(Version(
-      captures.get(1).and_then(|v| v.as_str().parse().ok())?,
-      captures.get(2).and_then(|v| v.as_str().parse().ok())?,
-      captures.get(3).and_then(|v| v.as_str().parse().ok())?,
-    ))
+  fn sibling(&self, name: &str) -> ModuleKey {
+    ModuleKey {
+      name: name.into(),
+      schema: self.schema.clone(),
+    }
   }
 }
 
-pub const BASE_MODULE_NAME: &str = "fuzion";
-
-impl<'a> Migrator<'a> {
-  pub fn new(
-    module_name: &str,
-    db_client: deadpool_postgres::Client,
-    migrations: Vec<Box<dyn Migration + 'a>>,
-  ) -> Migrator<'a> {
-    Migrator {
-      module_name: module_name.into(),
-      db_client,
-      migrations,
+impl fmt::Display for ModuleKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.schema {
+      Some(schema) => write!(f, "{}@{}", self.name, schema),
+      None => write!(f, "{}", self.name),
     }
   }
+}
 
-  pub async fn migrate(&mut self) -> Result<(), MigrationError> {
-    let version = self.get_version().await?;
+/// Advisory locks are session-level and the pool does not reset returned connections, so a
+/// migrator dropped while holding the lock detaches and closes its connection instead.
+struct MigratorClient {
+  client: Option<deadpool_postgres::Client>,
+  locks: AtomicU32,
+}
 
-    // Find migrations that are newer than current version.
-    let migrations: Vec<&Box<dyn Migration>> = self
-      .migrations
-      .iter()
-      .filter(|e| e.version() > version)
-      .collect();
+impl MigratorClient {
+  /// Counted before the lock is requested, as a cancelled request may still have been granted.
+  fn locking(&self) {
+    self.locks.fetch_add(1, Ordering::SeqCst);
+  }
 
-    // Perform each one.
-    for migration in &migrations {
-      info!(
-        "[{}] Migrating to {:?} ...",
-        self.module_name,
-        &migration.version()
-      );
+  fn unlocked(&self) {
+    self.locks.fetch_sub(1, Ordering::SeqCst);
+  }
+}
 
-      let mut txn = self.db_client.transaction().await?;
+impl Deref for MigratorClient {
+  type Target = deadpool_postgres::Client;
 
-      // If we fail, set a flag
-      let result = match migration.do_migration(&mut txn).await {
-        Ok(_) => Self::update_version(&txn, &self.module_name, &***migration).await,
-        Err(err) => Err(err),
-      };
+  fn deref(&self) -> &Self::Target {
+    self.client.as_ref().unwrap()
+  }
+}
 
-      if result.is_err() {
-        error!(
-          "[{}] Failed migration on version: {:?}",
-          self.module_name,
-          &migration.version()
-        );
+impl DerefMut for MigratorClient {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    self.client.as_mut().unwrap()
+  }
+}
 
-        return result;
-      }
+impl Drop for MigratorClient {
+  fn drop(&mut self) {
+    if *self.locks.get_mut() > 0 {
+      if let Some(client) = self.client.take() {
+        warn!("Closing a connection that still holds a migration lock");
 
-      txn.commit().await?;
+        drop(deadpool_postgres::Client::take(client));
+      }
     }
+  }
+}
 
-    Ok(())
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MigrationTimeouts {
+  pub lock_timeout: Option<Duration>,
+  pub statement_timeout: Option<Duration>,
+}
+
+impl MigrationTimeouts {
+  pub fn merge(self, other: MigrationTimeouts) -> MigrationTimeouts {
+    MigrationTimeouts {
+      lock_timeout: other.lock_timeout.or(self.lock_timeout),
+      statement_timeout: other.statement_timeout.or(self.statement_timeout),
+    }
   }
+}
 
-  pub async fn get_version(&self) -> Result<Version, MigrationError> {
-    self.initialize_versions().await?;
+struct StepSettings {
+  schema: Option<String>,
+  tables: MigratorTables,
+  timeouts: MigrationTimeouts,
+  lock_retries: u32,
+  lock_retry_backoff: Duration,
+}
 
-    // Try to get version, and if we fail, assume the database is uninitialized (0, 0, 0).
-    let rows = self
-      .db_client
-      .query(GET_VERSION_MODULE, &[&self.module_name])
-      .await?;
+impl StepSettings {
+  fn parameters(&self) -> Vec<(&'static str, String)> {
+    let timeout = |duration: Duration| format!("{}ms", duration.as_millis().max(1));
 
-    let version = match rows.first() {
-      Some(row) => Version(row.get(0), row.get(1), row.get(2)),
-      None => Version(0, 0, 0),
-    };
+    let mut parameters = vec![];
 
-    info!("[{}] Current version is: {:?}", self.module_name, &version);
+    if let Some(schema) = &self.schema {
+      parameters.push(("search_path", search_path(schema)));
+    }
+    if let Some(lock_timeout) = self.timeouts.lock_timeout {
+      parameters.push(("lock_timeout", timeout(lock_timeout)));
+    }
+    if let Some(statement_timeout) = self.timeouts.statement_timeout {
+      parameters.push(("statement_timeout", timeout(statement_timeout)));
+    }
 
-    Ok(version)
+    parameters
   }
+}
 
-  async fn update_version(
-    db_client: &deadpool_postgres::Transaction<'_>,
-    module_name: &str,
-    migration: &dyn Migration,
-  ) -> Result<(), MigrationError> {
-    let version = migration.version();
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigratorTables {
+  pub schema: String,
+  pub version_table: String,
+}
 
-    db_client
-      .execute(
-        UPDATE_MODULE_VERSION,
-        &[&module_name, &version.0, &version.1, &version.2],
-      )
-      .await?;
+impl Default for MigratorTables {
+  fn default() -> Self {
+    MigratorTables {
+      schema: String::from(DEFAULT_MIGRATOR_SCHEMA),
+      version_table: String::from(DEFAULT_VERSION_TABLE),
+    }
+  }
+}
 
-    Ok(())
+impl MigratorTables {
+  fn legacy() -> MigratorTables {
+    MigratorTables {
+      schema: String::from("public"),
+      version_table: String::from("version"),
+    }
   }
 
-  pub async fn initialize_versions(&self) -> Result<(), MigrationError> {
-    self.db_client.batch_execute(CREATE_MIGRATOR_SCHEMA).await?;
+  fn sql(&self, query: &str) -> String {
+    query
+      .replace("{schema}", &quote_ident(&self.schema))
+      .replace("{version_table}", &quote_ident(&self.version_table))
+  }
+}
 
-    // Check if old version table exists.
-    let has_old_version: bool = {
-      let rows = self.db_client.query(CHECK_OLD_VERSION_EXISTS, &[]).await?;
-      rows.first().unwrap().get(0)
+#[derive(Debug, PartialEq, Eq)]
+enum VersionTableLayout {
+  Missing,
+  NoModules,
+  NoSchemas,
+  Modules,
+  Unknown(Vec<String>),
+}
+
+impl VersionTableLayout {
+  fn from_columns(columns: Option<Vec<String>>) -> VersionTableLayout {
+    let Some(columns) = columns else {
+      return VersionTableLayout::Missing;
     };
 
-    if has_old_version {
-      self.db_client.batch_execute(MOVE_VERSION).await?;
+    // Legacy tables may carry columns of their own, only the ones the migrator uses matter.
+    let has = |column: &str| columns.iter().any(|e| e == column);
+
+    match (
+      has("major") && has("minor") && has("patch"),
+      has("module"),
+      has("schema"),
+    ) {
+      (true, true, true) => VersionTableLayout::Modules,
+      (true, true, false) => VersionTableLayout::NoSchemas,
+      (true, false, _) => VersionTableLayout::NoModules,
+      (false, _, _) => VersionTableLayout::Unknown(columns),
     }
+  }
+}
 
-    // Check that version table supports modules.
-    let has_modules: bool = {
-      let rows = self
-        .db_client
-        .query(CHECK_VERSION_MODULE_EXISTS, &[])
-        .await?;
-      rows.first().unwrap().get(0)
-    };
+#[derive(Clone, Copy, Debug)]
+enum Step {
+  Baseline,
+  Migration(usize),
+}
 
-    if !has_modules {
-      // Check that version table supports modules.
-      let has_version: bool = {
-        let rows = self.db_client.query(CHECK_VERSION_EXISTS, &[]).await?;
-        rows.first().unwrap().get(0)
-      };
+pub use fuzion_commons_macros::embed_migrations;
 
-      if has_version {
-        self
-          .db_client
-          .batch_execute(ADD_VERSION_MODULE_COLUMN)
-          .await?;
-      } else {
-        self.db_client.execute(CREATE_VERSION_TABLE, &[]).await?;
-      }
-    }
+impl Version {
+  pub fn from_filename(filename: &str) -> Option<Self> {
+    let file_name = Path::new(filename).file_name()?.to_str()?;
+    let (major, minor, patch) = parse_filename(file_name)?;
 
-    Ok(())
+    Some(Version::new(major, minor, patch))
   }
 }
 
-#[derive(Debug, Error)]
-pub enum MigrationInitError {
-  #[error(transparent)]
-  DatabaseConfigError(#[from] DatabaseConfigError),
-  #[error(transparent)]
-  DeadpoolPoolError(#[from] DeadpoolPoolError),
-  #[error(transparent)]
-  MigrationError(#[from] MigrationError),
-}
+pub const BASE_MODULE_NAME: &str = "fuzion";
 
-#[derive(Debug, Error)]
-pub enum MigrationError {
-  #[error("Could not initialize version table")]
-  CouldNotInitializeVersionTable,
-  #[error("Base version does not support modules, please upgrade")]
-  NoModules,
-  #[error("Interactive required.")]
-  InteractiveRequired,
-  #[error(transparent)]
-  IoError(#[from] std::io::Error),
-  #[error("Error: {0}")]
-  OtherError(String),
-  #[error(transparent)]
-  Postgres(#[from] tokio_postgres::Error),
-}
+pub const DEFAULT_MIGRATOR_SCHEMA: &str = "migrator";
 
-#[async_trait]
-pub trait Migration {
-  fn version(&self) -> Version;
-  async fn do_migration(
-    &self,
-    conn: &mut tokio_postgres::Transaction<'_>,
-  ) -> Result<(), MigrationError>;
-}
+pub const DEFAULT_VERSION_TABLE: &str = "version";
 
-#[macro_export]
-macro_rules! plain_migration {
-  ($arg:literal) => {
-    Box::new(fuzion_commons::migration::PlainMigration::new(
-      fuzion_commons::version::Version::from_filename($arg).unwrap(),
-      include_str!(concat!($arg)),
-    ));
-  };
-}
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
 
-pub struct PlainMigration {
-  version: Version,
-  query: &'static str,
-}
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-impl PlainMigration {
-  pub fn new(version: Version, query: &'static str) -> Self {
-    Self { version, query }
+pub const DEFAULT_LOCK_RETRIES: u32 = 3;
+
+pub const DEFAULT_LOCK_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+pub const DEFAULT_BATCH_SIZE: u64 = 1000;
+
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+impl<'a> Migrator<'a> {
+  pub fn new(
+    module_name: &str,
+    db_client: deadpool_postgres::Client,
+    migrations: Vec<Box<dyn Migration + 'a>>,
+  ) -> Migrator<'a> {
+    Migrator {
+      module: ModuleKey {
+        name: module_name.into(),
+        schema: None,
+      },
+      db_client: MigratorClient {
+        client: Some(db_client),
+        locks: AtomicU32::new(0),
+      },
+      migrations,
+      baseline: None,
+      repeatables: vec![],
+      seeds: vec![],
+      environment: None,
+      tables: MigratorTables::default(),
+      lint_policy: LintPolicy::default(),
+      lock_timeout: Some(DEFAULT_LOCK_TIMEOUT),
+      timeouts: MigrationTimeouts::default(),
+      lock_retries: DEFAULT_LOCK_RETRIES,
+      lock_retry_backoff: DEFAULT_LOCK_RETRY_BACKOFF,
+    }
   }
-}
 
-#[async_trait]
-impl Migration for PlainMigration {
-  fn version(&self) -> Version {
-    self.version
+  pub fn with_baseline(mut self, baseline: Box<dyn Migration + 'a>) -> Self {
+    self.baseline = Some(baseline);
+    self
   }
 
-  async fn do_migration(
-    &self,
-    conn: &mut tokio_postgres::Transaction<'_>,
-  ) -> Result<(), MigrationError> {
-    conn.batch_execute(self.query).await?;
+  pub fn with_repeatables(mut self, repeatables: Vec<RepeatableMigration>) -> Self {
+    self.repeatables = repeatables;
+    self
+  }
 
-    Ok(())
+  pub fn with_seeds(mut self, seeds: Vec<SeedSet>) -> Self {
+    self.seeds = seeds;
+    self
   }
-}
 
-const CHECK_VERSION_EXISTS: &str = r#"
+  /// Seeds are only applied once an environment is set, e.g. from `Environment::from_env`.
+  pub fn with_environment(mut self, environment: Option<Environment>) -> Self {
+    self.environment = environment;
+    self
+  }
 
-SELECT EXISTS (
-  SELECT
-    1
-  FROM information_schema.columns
-  WHERE
-    table_schema = 'fuzion'
-    AND table_name = 'version'
-);
+  /// Runs the migrations inside `schema`, by setting `search_path` for each transaction. The
+  /// version, and the modules that `requires` directives refer to, are tracked per schema.
+  pub fn with_schema(mut self, schema: &str) -> Self {
+    self.module.schema = Some(schema.into());
+    self
+  }
 
-"#;
+  pub fn with_tables(mut self, tables: MigratorTables) -> Self {
+    self.tables = tables;
+    self
+  }
 
-const CHECK_OLD_VERSION_EXISTS: &str = r#"
+  pub fn with_lint_policy(mut self, lint_policy: LintPolicy) -> Self {
+    self.lint_policy = lint_policy;
+    self
+  }
 
-SELECT EXISTS (
-  SELECT
-    1
-  FROM information_schema.columns
-  WHERE
-    table_schema = 'public'
-    AND table_name = 'version'
-);
+  pub fn with_lock_timeout(mut self, lock_timeout: Option<Duration>) -> Self {
+    self.lock_timeout = lock_timeout;
+    self
+  }
 
-"#;
+  pub fn with_timeouts(mut self, timeouts: MigrationTimeouts) -> Self {
+    self.timeouts = timeouts;
+    self
+  }
 
-const MOVE_VERSION: &str = r#"
+  pub fn with_lock_retries(mut self, retries: u32, backoff: Duration) -> Self {
+    self.lock_retries = retries;
+    self.lock_retry_backoff = backoff;
+    self
+  }
 
-ALTER TABLE public.version SET SCHEMA migrator;
+  pub async fn migrate(&mut self) -> Result<(), MigrationError> {
+    self.lock().await?;
+    let result = self.migrate_locked().await;
+    self.unlock().await;
 
-"#;
+    result
+  }
 
-const CHECK_VERSION_MODULE_EXISTS: &str = r#"
+  async fn migrate_locked(&mut self) -> Result<(), MigrationError> {
+    self.check_versions()?;
 
-SELECT EXISTS (
-  SELECT
-    1
-  FROM information_schema.columns
-  WHERE
-    table_schema = 'migrator'
-    AND table_name = 'version'
-    AND column_name = 'module'
-);
+    let version = self.get_version().await?;
 
-"#;
+    self.check_schema().await?;
+    self.check_dirty().await?;
 
-const GET_VERSION_MODULE: &str = r#"
+    self.verify_checksums(&version).await?;
 
-SELECT
-  major, minor, patch
-FROM
-  migrator.version
-WHERE
-  module = $1
+    let steps = self.pending_steps(&version, None);
 
-"#;
+    self.check_lint(&steps)?;
 
-const UPDATE_MODULE_VERSION: &str = r#"
+    // Perform each migration that is newer than the current version.
+    for step in steps {
+      self.check_requirements(self.step(step)).await?;
+      self.apply_step(step).await?;
+    }
 
-INSERT INTO migrator.version
-(module, major, minor, patch)
-VALUES
-($1, $2, $3, $4)
-ON CONFLICT (module)
-DO UPDATE SET major = $2, minor = $3, patch = $4;
+    self.apply_repeatables().await?;
+    self.apply_seeds().await
+  }
 
-"#;
+  async fn pending_repeatables(&self) -> Result<Vec<&RepeatableMigration>, MigrationError> {
+    let rows = match self.is_initialized().await? {
+      true => {
+        self
+          .db_client
+          .query(
+            &self.tables.sql(GET_MODULE_REPEATABLES),
+            &[&self.module.name, &self.module.schema()],
+          )
+          .await?
+      }
+      false => vec![],
+    };
 
-const ADD_VERSION_MODULE_COLUMN: &str = r#"
+    let applied: HashMap<&str, &str> = rows.iter().map(|row| (row.get(0), row.get(1))).collect();
 
-ALTER TABLE migrator.version
-  ADD COLUMN module VARCHAR(128);
+    Ok(
+      self
+        .repeatables
+        .iter()
+        .filter(|e| applied.get(e.name) != Some(&e.checksum.as_str()))
+        .collect(),
+    )
+  }
 
-UPDATE migrator.version
-SET module = 'fuzion';
+  async fn apply_repeatables(&mut self) -> Result<(), MigrationError> {
+    let pending: Vec<usize> = {
+      let pending = self.pending_repeatables().await?;
+
+      self
+        .repeatables
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| pending.iter().any(|p| p.name == e.name))
+        .map(|(idx, _)| idx)
+        .collect()
+    };
 
-ALTER TABLE migrator.version
-  ALTER COLUMN module SET NOT NULL;
+    for idx in pending {
+      let repeatable = &self.repeatables[idx];
 
-ALTER TABLE migrator.version
-  ADD PRIMARY KEY (module);
+      info!(
+        "[{}] Applying repeatable migration {} ...",
+        self.module, repeatable.name
+      );
 
-"#;
+      let settings = self.step_settings(MigrationTimeouts::default());
+      let txn = Self::begin(&mut self.db_client, &settings).await?;
+
+      let result = match txn.batch_execute(repeatable.query).await {
+        Ok(_) => txn
+          .execute(
+            &self.tables.sql(UPDATE_MODULE_REPEATABLE),
+            &[
+              &self.module.name,
+              &self.module.schema(),
+              &repeatable.name,
+              &repeatable.checksum,
+            ],
+          )
+          .await
+          .map(|_| ()),
+        Err(err) => Err(err),
+      };
 
-const CREATE_MIGRATOR_SCHEMA: &str = r#"
+      match result {
+        Ok(_) => txn.commit().await?,
+        Err(err) => {
+          let _ = txn.rollback().await;
 
-CREATE SCHEMA IF NOT EXISTS migrator;
+          error!(
+            "[{}] Failed repeatable migration {}: {}",
+            self.module,
+            repeatable.name,
+            fmt_pg_error(&err)
+          );
 
-"#;
+          return Err(err.into());
+        }
+      }
+    }
 
-const CREATE_VERSION_TABLE: &str = r#"
+    Ok(())
+  }
 
-CREATE TABLE migrator.version (
-    module varchar(128) NOT NULL PRIMARY KEY,
-    major smallint NOT NULL,
-    minor smallint NOT NULL,
-    patch smallint NOT NULL
-);
+  async fn apply_seeds(&mut self) -> Result<(), MigrationError> {
+    let Some(environment) = self.environment else {
+      if !self.seeds.is_empty() {
+        info!("[{}] No environment set, skipping seeds", self.module);
+      }
 
-"#;
+      return Ok(());
+    };
+
+    let applied: HashMap<String, String> = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_MODULE_SEEDS),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?
+      .iter()
+      .map(|row| (row.get(0), row.get(1)))
+      .collect();
+
+    let settings = self.step_settings(MigrationTimeouts::default());
+
+    for set in self.seeds.iter().filter(|set| set.applies_to(environment)) {
+      for seed in &set.seeds {
+        let name = format!("{}/{}", set.name, seed.name);
+
+        if applied.get(&name) == Some(&seed.checksum) {
+          continue;
+        }
+
+        info!(
+          "[{}] Applying {} seed {} ...",
+          self.module, environment, name
+        );
+
+        let txn = Self::begin(&mut self.db_client, &settings).await?;
+
+        let result = match &seed.kind {
+          SeedKind::Script(query) => txn.batch_execute(query).await.map_err(MigrationError::from),
+          SeedKind::Closure(seed) => seed(&txn).await,
+        };
+
+        let result = match result {
+          Ok(_) => txn
+            .execute(
+              &self.tables.sql(UPDATE_MODULE_SEED),
+              &[
+                &self.module.name,
+                &self.module.schema(),
+                &name,
+                &seed.checksum,
+              ],
+            )
+            .await
+            .map(|_| ())
+            .map_err(MigrationError::from),
+          Err(err) => Err(err),
+        };
+
+        match result {
+          Ok(_) => txn.commit().await?,
+          Err(err) => {
+            let _ = txn.rollback().await;
+
+            error!(
+              "[{}] Failed seed {}: {}",
+              self.module,
+              name,
+              describe_error(&err)
+            );
+
+            return Err(err);
+          }
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  fn pending_steps(&self, version: &Version, target: Option<&Version>) -> Vec<Step> {
+    let mut steps = vec![];
+    let mut from = version.clone();
+
+    if let Some(baseline) = &self.baseline {
+      if *version == Version::new(0, 0, 0)
+        && target.is_none_or(|target| baseline.version() <= *target)
+      {
+        steps.push(Step::Baseline);
+        from = baseline.version();
+      }
+    }
+
+    steps.extend(
+      self
+        .migrations
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.version() > from && target.is_none_or(|target| e.version() <= *target))
+        .map(|(idx, _)| Step::Migration(idx)),
+    );
+
+    steps
+  }
+
+  fn check_lint(&self, steps: &[Step]) -> Result<(), MigrationError> {
+    let findings: Vec<LintFinding> = steps
+      .iter()
+      .flat_map(|step| lint_migration(self.step(*step), &self.lint_policy))
+      .collect();
+
+    for finding in &findings {
+      match finding.level {
+        LintLevel::Deny => error!("[{}] {}", self.module, finding),
+        _ => warn!("[{}] {}", self.module, finding),
+      }
+    }
+
+    let denied: Vec<LintFinding> = findings
+      .into_iter()
+      .filter(|finding| finding.level == LintLevel::Deny)
+      .collect();
+
+    match denied.is_empty() {
+      true => Ok(()),
+      false => Err(MigrationError::Lint(denied)),
+    }
+  }
+
+  fn step(&self, step: Step) -> &dyn Migration {
+    match step {
+      Step::Baseline => &**self.baseline.as_ref().unwrap(),
+      Step::Migration(idx) => &*self.migrations[idx],
+    }
+  }
+
+  async fn apply_step(&mut self, step: Step) -> Result<(), MigrationError> {
+    let settings = self.step_settings(self.step(step).timeouts());
+
+    match step {
+      Step::Baseline => {
+        Self::apply_baseline(
+          &mut self.db_client,
+          &self.module,
+          &settings,
+          &**self.baseline.as_ref().unwrap(),
+        )
+        .await
+      }
+      Step::Migration(idx) => {
+        Self::apply(
+          &mut self.db_client,
+          &self.module,
+          &settings,
+          &*self.migrations[idx],
+        )
+        .await
+      }
+    }
+  }
+
+  fn step_settings(&self, timeouts: MigrationTimeouts) -> StepSettings {
+    StepSettings {
+      schema: self.module.schema.clone(),
+      tables: self.tables.clone(),
+      timeouts: self.timeouts.merge(timeouts),
+      lock_retries: self.lock_retries,
+      lock_retry_backoff: self.lock_retry_backoff,
+    }
+  }
+
+  pub async fn migrate_to(&mut self, target: Version) -> Result<(), MigrationError> {
+    self.lock().await?;
+    let result = self.migrate_to_locked(target).await;
+    self.unlock().await;
+
+    result
+  }
+
+  async fn migrate_to_locked(&mut self, target: Version) -> Result<(), MigrationError> {
+    self.check_versions()?;
+
+    let version = self.get_version().await?;
+
+    self.check_schema().await?;
+    self.check_dirty().await?;
+
+    self.verify_checksums(&version).await?;
+
+    let is_baseline = self
+      .baseline
+      .as_ref()
+      .is_some_and(|baseline| baseline.version() == target);
+
+    if target != Version::new(0, 0, 0)
+      && !is_baseline
+      && !self.migrations.iter().any(|e| e.version() == target)
+    {
+      return Err(MigrationError::UnknownVersion(target));
+    }
+
+    if target == version {
+      info!("[{}] Already at {}", self.module, target);
+
+      return Ok(());
+    }
+
+    if target > version {
+      let steps = self.pending_steps(&version, Some(&target));
+
+      self.check_lint(&steps)?;
+
+      for step in steps {
+        self.check_requirements(self.step(step)).await?;
+        self.apply_step(step).await?;
+      }
+
+      return Ok(());
+    }
+
+    // Migrations included in a baseline were never applied one by one, so there is nothing to
+    // revert them with.
+    if let Some(baseline) = self.get_baseline().await? {
+      if target < baseline {
+        return Err(MigrationError::Irreversible(baseline));
+      }
+    }
+
+    // Find migrations to revert, newest first.
+    let settings: Vec<StepSettings> = self
+      .migrations
+      .iter()
+      .filter(|e| e.version() > target && e.version() <= version)
+      .rev()
+      .map(|e| self.step_settings(e.timeouts()))
+      .collect();
+    let migrations: Vec<&Box<dyn Migration>> = self
+      .migrations
+      .iter()
+      .filter(|e| e.version() > target && e.version() <= version)
+      .rev()
+      .collect();
+
+    if let Some(migration) = migrations.iter().find(|e| !e.is_reversible()) {
+      return Err(MigrationError::Irreversible(migration.version()));
+    }
+
+    for (idx, migration) in migrations.iter().enumerate() {
+      // After reverting, the module sits at the next older migration (or the target).
+      let previous = migrations
+        .get(idx + 1)
+        .map(|e| e.version())
+        .unwrap_or_else(|| target.clone());
+
+      Self::revert(
+        &mut self.db_client,
+        &self.module,
+        &settings[idx],
+        &***migration,
+        previous,
+      )
+      .await?;
+    }
+
+    Ok(())
+  }
+
+  fn check_versions(&self) -> Result<(), MigrationError> {
+    self
+      .baseline
+      .iter()
+      .chain(&self.migrations)
+      .try_for_each(|e| check_version(&e.version()))
+  }
+
+  pub async fn plan(&self) -> Result<MigrationPlan, MigrationError> {
+    self.check_versions()?;
+
+    let version = self.read_version().await?;
+
+    let migrations = self
+      .pending_steps(&version, None)
+      .into_iter()
+      .map(|step| {
+        let e = self.step(step);
+
+        PlannedMigration {
+          version: e.version(),
+          source: e.source().map(String::from),
+          sql: e.sql().map(String::from),
+          checksum: e.checksum(),
+          transactional: e.is_transactional(),
+          baseline: matches!(step, Step::Baseline),
+        }
+      })
+      .collect();
+
+    let repeatables = self
+      .pending_repeatables()
+      .await?
+      .into_iter()
+      .map(|e| PlannedRepeatable {
+        name: e.name.into(),
+        source: e.source.map(String::from),
+        sql: e.query.into(),
+        checksum: e.checksum.clone(),
+      })
+      .collect();
+
+    Ok(MigrationPlan {
+      module_name: self.module.name.clone(),
+      schema: self.module.schema.clone(),
+      current: version,
+      migrations,
+      repeatables,
+      tables: self.tables.clone(),
+    })
+  }
+
+  /// Session-level, so concurrently starting instances migrate one at a time.
+  async fn lock(&self) -> Result<(), MigrationError> {
+    // A run cancelled earlier on this migrator may have left its locks behind.
+    if self.db_client.locks.load(Ordering::SeqCst) > 0 {
+      self.db_client.batch_execute(ADVISORY_UNLOCK_ALL).await?;
+      self.db_client.locks.store(0, Ordering::SeqCst);
+    }
+
+    let started = Instant::now();
+    let mut waiting = false;
+
+    loop {
+      self.db_client.locking();
+
+      let acquired: bool = {
+        let rows = self
+          .db_client
+          .query(
+            TRY_ADVISORY_LOCK,
+            &[&self.module.name, &self.module.schema()],
+          )
+          .await?;
+        rows.first().unwrap().get(0)
+      };
+
+      if !acquired {
+        self.db_client.unlocked();
+      }
+
+      if acquired {
+        if waiting {
+          info!(
+            "[{}] Acquired migration lock after {:?}",
+            self.module,
+            started.elapsed()
+          );
+        }
+
+        return Ok(());
+      }
+
+      if !waiting {
+        info!(
+          "[{}] Migration lock is held by another instance, waiting ...",
+          self.module
+        );
+
+        waiting = true;
+      }
+
+      if let Some(lock_timeout) = self.lock_timeout {
+        if started.elapsed() >= lock_timeout {
+          return Err(MigrationError::LockTimeout(
+            self.module.to_string(),
+            lock_timeout,
+          ));
+        }
+      }
+
+      sleep(LOCK_POLL_INTERVAL).await;
+    }
+  }
+
+  async fn unlock(&self) {
+    match self
+      .db_client
+      .execute(ADVISORY_UNLOCK, &[&self.module.name, &self.module.schema()])
+      .await
+    {
+      Ok(_) => self.db_client.unlocked(),
+      Err(err) => warn!(
+        "[{}] Failed to release migration lock: {}",
+        self.module,
+        fmt_pg_error(&err)
+      ),
+    }
+  }
+
+  pub async fn verify(&self) -> Result<Vec<Version>, MigrationError> {
+    let version = self.read_version().await?;
+    let (drifted, unrecorded) = self.compare_checksums(&version).await?;
+
+    if !drifted.is_empty() {
+      return Err(MigrationError::ChecksumMismatch(drifted));
+    }
+
+    Ok(unrecorded.into_iter().map(|(version, _)| version).collect())
+  }
+
+  async fn verify_checksums(&mut self, version: &Version) -> Result<(), MigrationError> {
+    let (drifted, unrecorded) = self.compare_checksums(version).await?;
+
+    if !drifted.is_empty() {
+      error!(
+        "[{}] Applied migrations have changed: {}",
+        self.module,
+        drifted.iter().join(", ")
+      );
+
+      return Err(MigrationError::ChecksumMismatch(drifted));
+    }
+
+    if unrecorded.is_empty() {
+      return Ok(());
+    }
+
+    let txn = self.db_client.transaction().await?;
+
+    for (applied, checksum) in &unrecorded {
+      txn
+        .execute(
+          &self.tables.sql(UPDATE_MODULE_CHECKSUM),
+          &[
+            &self.module.name,
+            &self.module.schema(),
+            &applied.major,
+            &applied.minor,
+            &applied.patch,
+            checksum,
+          ],
+        )
+        .await?;
+    }
+
+    txn.commit().await?;
+
+    info!(
+      "[{}] Recorded checksums of migrations applied before checksums were tracked: {}",
+      self.module,
+      unrecorded.iter().map(|(applied, _)| applied).join(", ")
+    );
+
+    Ok(())
+  }
+
+  async fn compare_checksums(
+    &self,
+    version: &Version,
+  ) -> Result<(Vec<Version>, Vec<(Version, String)>), MigrationError> {
+    let (rows, baseline) = match self.is_initialized().await? {
+      true => (
+        self
+          .db_client
+          .query(
+            &self.tables.sql(GET_MODULE_CHECKSUMS),
+            &[&self.module.name, &self.module.schema()],
+          )
+          .await?,
+        self.get_baseline_row().await?,
+      ),
+      false => (vec![], None),
+    };
+
+    let mut drifted = vec![];
+    let mut unrecorded = vec![];
+
+    if let (Some((version, stored)), Some(current)) = (&baseline, &self.baseline) {
+      match (stored, current.checksum()) {
+        (Some(stored), Some(checksum)) if current.version() == *version && *stored != checksum => {
+          drifted.push(version.clone())
+        }
+        _ => {}
+      }
+    }
+
+    // Migrations folded into the baseline were never recorded individually.
+    let applied_individually = |applied: Version| {
+      applied <= *version
+        && baseline
+          .as_ref()
+          .is_none_or(|(baseline, _)| applied > *baseline)
+    };
+
+    for migration in self
+      .migrations
+      .iter()
+      .filter(|e| applied_individually(e.version()))
+    {
+      let Some(checksum) = migration.checksum() else {
+        continue;
+      };
+
+      let applied = migration.version();
+      let stored = rows
+        .iter()
+        .find(|row| Version::new(row.get(0), row.get(1), row.get(2)) == applied)
+        .map(|row| row.get::<_, String>(3));
+
+      match stored {
+        Some(stored) if stored != checksum => drifted.push(applied),
+        Some(_) => {}
+        // Applied before checksums were tracked.
+        None => unrecorded.push((applied, checksum)),
+      }
+    }
+
+    Ok((drifted, unrecorded))
+  }
+
+  async fn apply(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+  ) -> Result<(), MigrationError> {
+    info!("[{}] Migrating to {} ...", module, migration.version());
+
+    let started = Instant::now();
+
+    let result = Self::run_step(
+      db_client,
+      module,
+      settings,
+      migration,
+      MigrationDirection::Up,
+      &migration.version(),
+    )
+    .await;
+
+    Self::record_history(
+      db_client,
+      &settings.tables,
+      module,
+      migration,
+      MigrationDirection::Up,
+      started.elapsed(),
+      result.as_ref().err(),
+    )
+    .await;
+
+    if let Err(err) = result {
+      error!(
+        "[{}] Failed migration on version: {}: {}",
+        module,
+        migration.version(),
+        describe_error(&err)
+      );
+
+      return Err(err);
+    }
+
+    Ok(())
+  }
+
+  async fn apply_baseline(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    baseline: &dyn Migration,
+  ) -> Result<(), MigrationError> {
+    let version = baseline.version();
+
+    info!("[{}] Applying baseline {} ...", module, version);
+
+    let started = Instant::now();
+
+    let result = match baseline.is_transactional() {
+      true => {
+        let mut txn = Self::begin(db_client, settings).await?;
+
+        let result = match baseline.do_migration(&mut txn).await {
+          Ok(_) => match Self::update_version(&txn, &settings.tables, module, &version).await {
+            Ok(_) => txn
+              .execute(
+                &settings.tables.sql(INSERT_MODULE_BASELINE),
+                &[
+                  &module.name,
+                  &module.schema(),
+                  &version.major,
+                  &version.minor,
+                  &version.patch,
+                  &baseline.checksum(),
+                ],
+              )
+              .await
+              .map(|_| ())
+              .map_err(MigrationError::from),
+            Err(err) => Err(err),
+          },
+          Err(err) => Err(err),
+        };
+
+        match result {
+          Ok(_) => txn.commit().await.map_err(MigrationError::from),
+          Err(err) => {
+            let _ = txn.rollback().await;
+
+            Err(err)
+          }
+        }
+      }
+      false => Err(MigrationError::TransactionRequired(version.clone())),
+    };
+
+    Self::record_history(
+      db_client,
+      &settings.tables,
+      module,
+      baseline,
+      MigrationDirection::Up,
+      started.elapsed(),
+      result.as_ref().err(),
+    )
+    .await;
+
+    if let Err(err) = result {
+      error!(
+        "[{}] Failed applying baseline {}: {}",
+        module,
+        version,
+        describe_error(&err)
+      );
+
+      return Err(err);
+    }
+
+    Ok(())
+  }
+
+  pub async fn get_baseline(&self) -> Result<Option<Version>, MigrationError> {
+    if !self.is_initialized().await? {
+      return Ok(None);
+    }
+
+    Ok(self.get_baseline_row().await?.map(|(version, _)| version))
+  }
+
+  async fn get_baseline_row(&self) -> Result<Option<(Version, Option<String>)>, MigrationError> {
+    let rows = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_MODULE_BASELINE),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?;
+
+    Ok(
+      rows
+        .first()
+        .map(|row| (Version::new(row.get(0), row.get(1), row.get(2)), row.get(3))),
+    )
+  }
+
+  async fn revert(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+    previous: Version,
+  ) -> Result<(), MigrationError> {
+    info!(
+      "[{}] Reverting {} to {} ...",
+      module,
+      migration.version(),
+      previous
+    );
+
+    let started = Instant::now();
+
+    let result = Self::run_step(
+      db_client,
+      module,
+      settings,
+      migration,
+      MigrationDirection::Down,
+      &previous,
+    )
+    .await;
+
+    Self::record_history(
+      db_client,
+      &settings.tables,
+      module,
+      migration,
+      MigrationDirection::Down,
+      started.elapsed(),
+      result.as_ref().err(),
+    )
+    .await;
+
+    if let Err(err) = result {
+      error!(
+        "[{}] Failed reverting version: {}: {}",
+        module,
+        migration.version(),
+        describe_error(&err)
+      );
+
+      return Err(err);
+    }
+
+    Ok(())
+  }
+
+  async fn run_step(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+    direction: MigrationDirection,
+    target: &Version,
+  ) -> Result<(), MigrationError> {
+    let transactional = match direction {
+      MigrationDirection::Up => migration.is_transactional(),
+      MigrationDirection::Down => migration.is_revert_transactional(),
+    };
+    let retryable = transactional || migration.as_batched().is_some();
+    let mut attempts = 1;
+
+    loop {
+      let result = match (direction, migration.as_batched(), transactional) {
+        (MigrationDirection::Up, Some(batched), _) => {
+          Self::run_batched(db_client, module, settings, migration, batched).await
+        }
+        (MigrationDirection::Up, None, true) => {
+          Self::apply_in_transaction(db_client, module, settings, migration).await
+        }
+        (MigrationDirection::Down, _, true) => {
+          Self::revert_in_transaction(db_client, module, settings, migration, target).await
+        }
+        (_, _, false) => {
+          Self::run_without_transaction(db_client, module, settings, migration, direction, target)
+            .await
+        }
+      };
+
+      match result {
+        Err(err) if is_lock_timeout(&err) && retryable && attempts <= settings.lock_retries => {
+          let delay = settings.lock_retry_backoff * 2u32.saturating_pow(attempts - 1);
+
+          warn!(
+            "[{}] {} timed out waiting for a lock, retrying in {:?} ({}/{})",
+            module,
+            migration.version(),
+            delay,
+            attempts,
+            settings.lock_retries
+          );
+
+          sleep(delay).await;
+          attempts += 1;
+        }
+        Err(err) if is_lock_timeout(&err) => {
+          return Err(Self::blocked(db_client, module, migration, attempts).await);
+        }
+        result => return result,
+      }
+    }
+  }
+
+  async fn blocked(
+    db_client: &deadpool_postgres::Client,
+    module: &ModuleKey,
+    migration: &dyn Migration,
+    attempts: u32,
+  ) -> MigrationError {
+    let relations: Option<Vec<String>> = migration
+      .sql()
+      .map(|sql| tokenize(sql).into_iter().map(identifier).collect());
+
+    let blocker = match db_client.query(GET_BLOCKING_LOCK, &[&relations]).await {
+      Ok(rows) => rows
+        .first()
+        .map(|row| (row.get::<_, String>(0), row.get::<_, i32>(1))),
+      Err(err) => {
+        warn!(
+          "[{}] Failed to look up the blocking lock: {}",
+          module,
+          fmt_pg_error(&err)
+        );
+
+        None
+      }
+    };
+
+    let (relation, pid) = blocker.unzip();
+
+    MigrationError::Blocked {
+      module: module.to_string(),
+      version: migration.version(),
+      relation,
+      pid,
+      attempts,
+    }
+  }
+
+  async fn apply_in_transaction(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+  ) -> Result<(), MigrationError> {
+    let mut txn = Self::begin(db_client, settings).await?;
+
+    // If we fail, set a flag
+    let result = match migration.do_migration(&mut txn).await {
+      Ok(_) => {
+        match Self::update_version(&txn, &settings.tables, module, &migration.version()).await {
+          Ok(_) => Self::update_checksum(&txn, &settings.tables, module, migration).await,
+          Err(err) => Err(err),
+        }
+      }
+      Err(err) => Err(err),
+    };
+
+    match result {
+      Ok(_) => Ok(txn.commit().await?),
+      Err(err) => {
+        let _ = txn.rollback().await;
+
+        Err(err)
+      }
+    }
+  }
+
+  async fn revert_in_transaction(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+    previous: &Version,
+  ) -> Result<(), MigrationError> {
+    let mut txn = Self::begin(db_client, settings).await?;
+
+    let result = match migration.revert_migration(&mut txn).await {
+      Ok(_) => match Self::update_version(&txn, &settings.tables, module, previous).await {
+        Ok(_) => Self::delete_checksum(&txn, &settings.tables, module, &migration.version()).await,
+        Err(err) => Err(err),
+      },
+      Err(err) => Err(err),
+    };
+
+    match result {
+      Ok(_) => Ok(txn.commit().await?),
+      Err(err) => {
+        let _ = txn.rollback().await;
+
+        Err(err)
+      }
+    }
+  }
+
+  async fn begin<'c>(
+    db_client: &'c mut deadpool_postgres::Client,
+    settings: &StepSettings,
+  ) -> Result<deadpool_postgres::Transaction<'c>, MigrationError> {
+    let txn = db_client.transaction().await?;
+
+    for (name, value) in settings.parameters() {
+      txn.execute(SET_CONFIG, &[&name, &value, &true]).await?;
+    }
+
+    Ok(txn)
+  }
+
+  /// Steps outside of a transaction set `search_path` and timeouts for the session, which are
+  /// reset afterwards so that the pooled connection does not keep them.
+  async fn set_session(
+    db_client: &deadpool_postgres::Client,
+    settings: &StepSettings,
+  ) -> Result<(), MigrationError> {
+    for (name, value) in settings.parameters() {
+      if let Err(err) = db_client
+        .execute(SET_CONFIG, &[&name, &value, &false])
+        .await
+      {
+        Self::reset_session(db_client, settings).await;
+
+        return Err(err.into());
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn reset_session(db_client: &deadpool_postgres::Client, settings: &StepSettings) {
+    for (name, _) in settings.parameters() {
+      if let Err(err) = db_client.batch_execute(&format!("RESET {name}")).await {
+        warn!("Failed to reset {}: {}", name, fmt_pg_error(&err));
+      }
+    }
+  }
+
+  /// The module is dirty for the duration of the step, so a failure halfway through blocks
+  /// further migrations until `resolve_dirty` is called.
+  async fn run_without_transaction(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+    direction: MigrationDirection,
+    target: &Version,
+  ) -> Result<(), MigrationError> {
+    let version = migration.version();
+
+    // A rejected setting fails the step before the module is marked dirty.
+    Self::set_session(db_client, settings).await?;
+
+    if let Err(err) = db_client
+      .execute(
+        &settings.tables.sql(MARK_MODULE_DIRTY),
+        &[
+          &module.name,
+          &module.schema(),
+          &version.major,
+          &version.minor,
+          &version.patch,
+          &direction.as_str(),
+          &target.major,
+          &target.minor,
+          &target.patch,
+        ],
+      )
+      .await
+    {
+      Self::reset_session(db_client, settings).await;
+
+      return Err(err.into());
+    }
+
+    let result = match direction {
+      MigrationDirection::Up => migration.do_migration_no_transaction(db_client).await,
+      MigrationDirection::Down => migration.revert_migration_no_transaction(db_client).await,
+    };
+
+    Self::reset_session(db_client, settings).await;
+
+    if let Err(err) = result {
+      Self::record_dirty_error(db_client, settings, module, &describe_error(&err)).await;
+
+      return Err(err);
+    }
+
+    // The step ran, so a failure here must not look like an interrupted step to the operator.
+    if let Err(err) =
+      Self::finish_without_transaction(db_client, module, settings, migration, direction, target)
+        .await
+    {
+      let error = format!(
+        "step completed, but recording it failed: {}",
+        describe_error(&err)
+      );
+      Self::record_dirty_error(db_client, settings, module, &error).await;
+
+      return Err(err);
+    }
+
+    Ok(())
+  }
+
+  async fn finish_without_transaction(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+    direction: MigrationDirection,
+    target: &Version,
+  ) -> Result<(), MigrationError> {
+    let version = migration.version();
+    let txn = db_client.transaction().await?;
+
+    Self::update_version(&txn, &settings.tables, module, target).await?;
+
+    match direction {
+      MigrationDirection::Up => {
+        Self::update_checksum(&txn, &settings.tables, module, migration).await?
+      }
+      MigrationDirection::Down => {
+        Self::delete_checksum(&txn, &settings.tables, module, &version).await?
+      }
+    }
+
+    txn
+      .execute(
+        &settings.tables.sql(CLEAR_MODULE_DIRTY),
+        &[&module.name, &module.schema()],
+      )
+      .await?;
+    txn.commit().await?;
+
+    Ok(())
+  }
+
+  async fn record_dirty_error(
+    db_client: &deadpool_postgres::Client,
+    settings: &StepSettings,
+    module: &ModuleKey,
+    error: &str,
+  ) {
+    if let Err(err) = db_client
+      .execute(
+        &settings.tables.sql(UPDATE_DIRTY_ERROR),
+        &[&module.name, &module.schema(), &error],
+      )
+      .await
+    {
+      warn!(
+        "[{}] Failed to record dirty state: {}",
+        module,
+        fmt_pg_error(&err)
+      );
+    }
+  }
+
+  async fn run_batched(
+    db_client: &mut deadpool_postgres::Client,
+    module: &ModuleKey,
+    settings: &StepSettings,
+    migration: &dyn Migration,
+    batched: &dyn BatchedMigration,
+  ) -> Result<(), MigrationError> {
+    let version = migration.version();
+
+    let (mut cursor, mut rows_done) = {
+      let rows = db_client
+        .query(
+          &settings.tables.sql(GET_MODULE_CURSOR),
+          &[
+            &module.name,
+            &module.schema(),
+            &version.major,
+            &version.minor,
+            &version.patch,
+          ],
+        )
+        .await?;
+
+      match rows.first() {
+        Some(row) => (Some(row.get::<_, String>(0)), row.get::<_, i64>(1) as u64),
+        None => (None, 0),
+      }
+    };
+
+    if cursor.is_some() {
+      info!("[{}] Resuming {} after {} rows", module, version, rows_done);
+    }
+
+    Self::set_session(db_client, settings).await?;
+    let total = batched.total(db_client).await;
+    Self::reset_session(db_client, settings).await;
+    let total = total?;
+    let batch_size = batched.batch_size();
+    let started = Instant::now();
+    let mut rows_run = 0;
+    let mut logged = Instant::now();
+
+    loop {
+      let mut txn = Self::begin(db_client, settings).await?;
+
+      let batch = match batched
+        .run_batch(&mut txn, cursor.as_deref(), batch_size)
+        .await
+      {
+        Ok(batch) => batch,
+        Err(err) => {
+          let _ = txn.rollback().await;
+
+          return Err(err);
+        }
+      };
+
+      rows_done += batch.rows;
+      rows_run += batch.rows;
+
+      match &batch.cursor {
+        Some(next) => {
+          txn
+            .execute(
+              &settings.tables.sql(UPDATE_MODULE_CURSOR),
+              &[
+                &module.name,
+                &module.schema(),
+                &version.major,
+                &version.minor,
+                &version.patch,
+                next,
+                &(rows_done as i64),
+              ],
+            )
+            .await?;
+        }
+        None => {
+          Self::update_version(&txn, &settings.tables, module, &version).await?;
+          Self::update_checksum(&txn, &settings.tables, module, migration).await?;
+
+          txn
+            .execute(
+              &settings.tables.sql(DELETE_MODULE_CURSOR),
+              &[
+                &module.name,
+                &module.schema(),
+                &version.major,
+                &version.minor,
+                &version.patch,
+              ],
+            )
+            .await?;
+        }
+      }
+
+      txn.commit().await?;
+
+      let Some(next) = batch.cursor else {
+        info!(
+          "[{}] Finished {}, {} rows in {:?}",
+          module,
+          version,
+          rows_done,
+          started.elapsed()
+        );
+
+        return Ok(());
+      };
+
+      cursor = Some(next);
+
+      if logged.elapsed() >= PROGRESS_LOG_INTERVAL {
+        let rate = rows_run as f64 / started.elapsed().as_secs_f64();
+        let eta = total
+          .filter(|_| rate > 0.0)
+          .map(|total| {
+            let remaining = total.saturating_sub(rows_done) as f64 / rate;
+
+            format!(", ETA {:?}", Duration::from_secs(remaining.ceil() as u64))
+          })
+          .unwrap_or_default();
+
+        info!(
+          "[{}] {}: {}{} rows done, {:.0} rows/s{}",
+          module,
+          version,
+          rows_done,
+          total.map(|total| format!("/{total}")).unwrap_or_default(),
+          rate,
+          eta
+        );
+
+        logged = Instant::now();
+      }
+    }
+  }
+
+  pub async fn dirty(&self) -> Result<Option<DirtyMigration>, MigrationError> {
+    if !self.is_initialized().await? {
+      return Ok(None);
+    }
+
+    let rows = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_MODULE_DIRTY),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?;
+
+    Ok(rows.first().map(|row| DirtyMigration {
+      version: Version::new(row.get(0), row.get(1), row.get(2)),
+      direction: match row.get::<_, &str>(3) {
+        "down" => MigrationDirection::Down,
+        _ => MigrationDirection::Up,
+      },
+      error: row.get(4),
+    }))
+  }
+
+  /// With a missing schema, `search_path` would fall through to `public` and the migrations
+  /// would silently run there.
+  async fn check_schema(&self) -> Result<(), MigrationError> {
+    let Some(schema) = &self.module.schema else {
+      return Ok(());
+    };
+
+    let exists: bool = {
+      let rows = self.db_client.query(CHECK_SCHEMA_EXISTS, &[schema]).await?;
+      rows.first().unwrap().get(0)
+    };
+
+    match exists {
+      true => Ok(()),
+      false => Err(MigrationError::UnknownSchema(schema.clone())),
+    }
+  }
+
+  async fn check_dirty(&self) -> Result<(), MigrationError> {
+    match self.dirty().await? {
+      Some(dirty) => {
+        error!(
+          "[{}] Module is dirty after non-transactional migration {} ({}): {}",
+          self.module,
+          dirty.version,
+          dirty.direction.as_str(),
+          dirty.error.as_deref().unwrap_or("interrupted")
+        );
+
+        Err(MigrationError::Dirty(
+          self.module.to_string(),
+          dirty.version,
+        ))
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// `completed` moves the module to the version the failed step would have produced, after an
+  /// operator finished it by hand, otherwise its partial effects must have been undone.
+  pub async fn resolve_dirty(&mut self, completed: bool) -> Result<(), MigrationError> {
+    self.initialize_versions().await?;
+
+    let rows = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_MODULE_DIRTY),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?;
+
+    let Some(row) = rows.first() else {
+      return Ok(());
+    };
+
+    let version = Version::new(row.get(0), row.get(1), row.get(2));
+    let direction: String = row.get(3);
+    let target = Version::new(row.get(5), row.get(6), row.get(7));
+
+    let txn = self.db_client.transaction().await?;
+
+    if completed {
+      Self::update_version(&txn, &self.tables, &self.module, &target).await?;
+
+      match (
+        direction.as_str(),
+        self.migrations.iter().find(|e| e.version() == version),
+      ) {
+        ("up", Some(migration)) => {
+          Self::update_checksum(&txn, &self.tables, &self.module, &**migration).await?
+        }
+        ("down", _) => Self::delete_checksum(&txn, &self.tables, &self.module, &version).await?,
+        _ => {}
+      }
+    }
+
+    txn
+      .execute(
+        &self.tables.sql(CLEAR_MODULE_DIRTY),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?;
+    txn.commit().await?;
+
+    info!(
+      "[{}] Resolved dirty migration {} ({}), completed: {}",
+      self.module, version, direction, completed
+    );
+
+    Ok(())
+  }
+
+  /// Records an attempted migration step. Failures are logged rather than returned so that
+  /// bookkeeping problems never mask the outcome of the migration itself.
+  async fn record_history(
+    db_client: &deadpool_postgres::Client,
+    tables: &MigratorTables,
+    module: &ModuleKey,
+    migration: &dyn Migration,
+    direction: MigrationDirection,
+    duration: Duration,
+    error: Option<&MigrationError>,
+  ) {
+    let version = migration.version();
+    let duration_ms = duration.as_millis() as i64;
+    let error = error.map(describe_error);
+
+    if let Err(err) = db_client
+      .execute(
+        &tables.sql(INSERT_HISTORY),
+        &[
+          &module.name,
+          &module.schema(),
+          &version.major,
+          &version.minor,
+          &version.patch,
+          &direction.as_str(),
+          &duration_ms,
+          &migration.checksum(),
+          &error.is_none(),
+          &error,
+          &hostname(),
+        ],
+      )
+      .await
+    {
+      warn!(
+        "[{}] Failed to record migration history: {}",
+        module,
+        fmt_pg_error(&err)
+      );
+    }
+  }
+
+  pub async fn history(&self) -> Result<Vec<MigrationRecord>, MigrationError> {
+    if !self.is_initialized().await? {
+      return Ok(vec![]);
+    }
+
+    let rows = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_MODULE_HISTORY),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?;
+
+    Ok(
+      rows
+        .iter()
+        .map(|row| MigrationRecord {
+          module: row.get(0),
+          schema: row.get(1),
+          version: Version::new(row.get(2), row.get(3), row.get(4)),
+          direction: match row.get::<_, &str>(5) {
+            "down" => MigrationDirection::Down,
+            _ => MigrationDirection::Up,
+          },
+          applied_at: row.get(6),
+          duration: Duration::from_millis(row.get::<_, i64>(7) as u64),
+          checksum: row.get(8),
+          success: row.get(9),
+          error: row.get(10),
+          host: row.get(11),
+          application_name: row.get(12),
+        })
+        .collect(),
+    )
+  }
+
+  pub async fn require_version(
+    &self,
+    compatible: RangeInclusive<Version>,
+  ) -> Result<Version, MigrationError> {
+    let current = self.read_version().await?;
+
+    if current < *compatible.start() {
+      return Err(MigrationError::SchemaTooOld {
+        module: self.module.to_string(),
+        current,
+        required: compatible.start().clone(),
+      });
+    }
+
+    if current > *compatible.end() {
+      return Err(MigrationError::SchemaTooNew {
+        module: self.module.to_string(),
+        current,
+        supported: compatible.end().clone(),
+      });
+    }
+
+    Ok(current)
+  }
+
+  pub async fn wait_for_version(
+    &self,
+    compatible: RangeInclusive<Version>,
+    timeout: Option<Duration>,
+  ) -> Result<Version, MigrationError> {
+    let started = Instant::now();
+    let mut waiting = false;
+
+    loop {
+      match self.require_version(compatible.clone()).await {
+        Err(err @ MigrationError::SchemaTooOld { .. })
+          if timeout.is_none_or(|timeout| started.elapsed() < timeout) =>
+        {
+          if !waiting {
+            info!("[{}] Waiting for migrations: {}", self.module, err);
+
+            waiting = true;
+          }
+
+          sleep(LOCK_POLL_INTERVAL).await;
+        }
+        result => return result,
+      }
+    }
+  }
+
+  pub async fn get_version(&self) -> Result<Version, MigrationError> {
+    self.initialize_versions().await?;
+
+    // Try to get version, and if we fail, assume the database is uninitialized (0, 0, 0).
+    let rows = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_VERSION_MODULE),
+        &[&self.module.name, &self.module.schema()],
+      )
+      .await?;
+
+    let version = match rows.first() {
+      Some(row) => Version::new(row.get(0), row.get(1), row.get(2)),
+      None => Version::new(0, 0, 0),
+    };
+
+    info!("[{}] Current version is: {}", self.module, version);
+
+    Ok(version)
+  }
+
+  async fn read_version(&self) -> Result<Version, MigrationError> {
+    let legacy = MigratorTables::legacy();
+    let mut tables = &self.tables;
+    let mut layout = self
+      .version_table_layout(&tables.schema, &tables.version_table)
+      .await?;
+
+    // Until `initialize_versions` moves it, the version is still in `public.version`.
+    if layout == VersionTableLayout::Missing && *tables != legacy {
+      tables = &legacy;
+      layout = self
+        .version_table_layout(&tables.schema, &tables.version_table)
+        .await?;
+    }
+
+    let rows = match layout {
+      VersionTableLayout::Missing => return Ok(Version::new(0, 0, 0)),
+      VersionTableLayout::Modules => {
+        self
+          .db_client
+          .query(
+            &tables.sql(GET_VERSION_MODULE),
+            &[&self.module.name, &self.module.schema()],
+          )
+          .await?
+      }
+      VersionTableLayout::NoModules if self.module.name != BASE_MODULE_NAME => {
+        return Err(MigrationError::NoModules)
+      }
+      // Older layouts never held the version of a module in a schema.
+      VersionTableLayout::NoModules | VersionTableLayout::NoSchemas
+        if self.module.schema.is_some() =>
+      {
+        return Ok(Version::new(0, 0, 0))
+      }
+      VersionTableLayout::NoModules => {
+        self
+          .db_client
+          .query(&tables.sql(GET_VERSION_NO_MODULE), &[])
+          .await?
+      }
+      VersionTableLayout::NoSchemas => {
+        self
+          .db_client
+          .query(&tables.sql(GET_VERSION_NO_SCHEMA), &[&self.module.name])
+          .await?
+      }
+      VersionTableLayout::Unknown(_) => return Err(MigrationError::CouldNotInitializeVersionTable),
+    };
+
+    Ok(match rows.first() {
+      Some(row) => Version::new(row.get(0), row.get(1), row.get(2)),
+      None => Version::new(0, 0, 0),
+    })
+  }
+
+  async fn get_module_version(&self, module: &ModuleKey) -> Result<Version, MigrationError> {
+    let rows = self
+      .db_client
+      .query(
+        &self.tables.sql(GET_VERSION_MODULE),
+        &[&module.name, &module.schema()],
+      )
+      .await?;
+
+    Ok(match rows.first() {
+      Some(row) => Version::new(row.get(0), row.get(1), row.get(2)),
+      None => Version::new(0, 0, 0),
+    })
+  }
+
+  async fn check_requirements(&self, migration: &dyn Migration) -> Result<(), MigrationError> {
+    for requirement in migration.requires() {
+      let required = requirement.as_version();
+      let current = self
+        .get_module_version(&self.module.sibling(&requirement.0))
+        .await?;
+
+      if current < required {
+        return Err(MigrationError::UnsatisfiedRequirement {
+          module: self.module.to_string(),
+          version: migration.version(),
+          requires: requirement.0,
+          required,
+          current,
+        });
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn update_version(
+    db_client: &deadpool_postgres::Transaction<'_>,
+    tables: &MigratorTables,
+    module: &ModuleKey,
+    version: &Version,
+  ) -> Result<(), MigrationError> {
+    db_client
+      .execute(
+        &tables.sql(UPDATE_MODULE_VERSION),
+        &[
+          &module.name,
+          &module.schema(),
+          &version.major,
+          &version.minor,
+          &version.patch,
+        ],
+      )
+      .await?;
+
+    Ok(())
+  }
+
+  async fn update_checksum(
+    db_client: &deadpool_postgres::Transaction<'_>,
+    tables: &MigratorTables,
+    module: &ModuleKey,
+    migration: &dyn Migration,
+  ) -> Result<(), MigrationError> {
+    let version = migration.version();
+
+    if let Some(checksum) = migration.checksum() {
+      db_client
+        .execute(
+          &tables.sql(UPDATE_MODULE_CHECKSUM),
+          &[
+            &module.name,
+            &module.schema(),
+            &version.major,
+            &version.minor,
+            &version.patch,
+            &checksum,
+          ],
+        )
+        .await?;
+    }
+
+    Ok(())
+  }
+
+  async fn delete_checksum(
+    db_client: &deadpool_postgres::Transaction<'_>,
+    tables: &MigratorTables,
+    module: &ModuleKey,
+    version: &Version,
+  ) -> Result<(), MigrationError> {
+    db_client
+      .execute(
+        &tables.sql(DELETE_MODULE_CHECKSUM),
+        &[
+          &module.name,
+          &module.schema(),
+          &version.major,
+          &version.minor,
+          &version.patch,
+        ],
+      )
+      .await?;
+
+    Ok(())
+  }
+
+  pub async fn initialize_versions(&self) -> Result<(), MigrationError> {
+    // Migrators of different modules run concurrently, and `IF NOT EXISTS` does not protect
+    // against concurrent creation.
+    self.db_client.locking();
+    self
+      .db_client
+      .batch_execute(INITIALIZE_ADVISORY_LOCK)
+      .await?;
+
+    let result = self.initialize_versions_locked().await;
+
+    match self
+      .db_client
+      .batch_execute(INITIALIZE_ADVISORY_UNLOCK)
+      .await
+    {
+      Ok(_) => self.db_client.unlocked(),
+      Err(err) => warn!(
+        "[{}] Failed to release initialization lock: {}",
+        self.module,
+        fmt_pg_error(&err)
+      ),
+    }
+
+    result
+  }
+
+  /// The primary key moves to `(module, schema)`. It keeps its name when the table is renamed,
+  /// so it is looked up rather than assumed.
+  async fn add_version_schema_column(&self, tables: &MigratorTables) -> Result<(), MigrationError> {
+    let mut batch = tables.sql(ADD_VERSION_SCHEMA_COLUMN);
+
+    for row in self
+      .db_client
+      .query(GET_PRIMARY_KEYS, &[&tables.schema, &tables.version_table])
+      .await?
+    {
+      batch += &format!(
+        "{} {};\n",
+        tables.sql(DROP_VERSION_CONSTRAINT),
+        quote_ident(row.get(0))
+      );
+    }
+
+    batch += &tables.sql(ADD_VERSION_PRIMARY_KEY);
+
+    self.db_client.batch_execute(&batch).await?;
+
+    Ok(())
+  }
+
+  async fn initialize_versions_locked(&self) -> Result<(), MigrationError> {
+    let tables = &self.tables;
+
+    self
+      .db_client
+      .batch_execute(&tables.sql(CREATE_MIGRATOR_SCHEMA))
+      .await?;
+
+    let mut layout = self
+      .version_table_layout(&tables.schema, &tables.version_table)
+      .await?;
+
+    // Versions were kept in `public.version` before the migrator had a schema of its own.
+    if layout == VersionTableLayout::Missing && *tables != MigratorTables::legacy() {
+      let legacy = self.version_table_layout("public", "version").await?;
+
+      if matches!(
+        legacy,
+        VersionTableLayout::NoModules | VersionTableLayout::NoSchemas | VersionTableLayout::Modules
+      ) {
+        info!(
+          "[{}] Moving legacy version table public.version to {}.{}",
+          self.module, tables.schema, tables.version_table
+        );
+
+        self
+          .db_client
+          .batch_execute(&tables.sql(MOVE_LEGACY_VERSION))
+          .await?;
+
+        if tables.version_table != "version" {
+          self
+            .db_client
+            .batch_execute(&tables.sql(RENAME_LEGACY_VERSION))
+            .await?;
+        }
+
+        layout = legacy;
+      }
+    }
+
+    match layout {
+      VersionTableLayout::Missing => {
+        self
+          .db_client
+          .batch_execute(&tables.sql(CREATE_VERSION_TABLE))
+          .await?;
+      }
+      // The versions of a table without modules belong to the base module, so only it may
+      // upgrade the table.
+      VersionTableLayout::NoModules if self.module.name != BASE_MODULE_NAME => {
+        return Err(MigrationError::NoModules);
+      }
+      VersionTableLayout::NoModules => {
+        self
+          .db_client
+          .batch_execute(&tables.sql(ADD_VERSION_MODULE_COLUMN))
+          .await?;
+        self.add_version_schema_column(tables).await?;
+      }
+      VersionTableLayout::NoSchemas => self.add_version_schema_column(tables).await?,
+      VersionTableLayout::Modules => {}
+      VersionTableLayout::Unknown(columns) => {
+        error!(
+          "[{}] Version table {}.{} has unexpected columns: {}",
+          self.module,
+          tables.schema,
+          tables.version_table,
+          columns.join(", ")
+        );
+
+        return Err(MigrationError::CouldNotInitializeVersionTable);
+      }
+    }
+
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_CHECKSUM_TABLE))
+      .await?;
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_HISTORY_TABLE))
+      .await?;
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_DIRTY_TABLE))
+      .await?;
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_BASELINE_TABLE))
+      .await?;
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_REPEATABLE_TABLE))
+      .await?;
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_CURSOR_TABLE))
+      .await?;
+    self
+      .db_client
+      .batch_execute(&self.tables.sql(CREATE_SEED_TABLE))
+      .await?;
+
+    Ok(())
+  }
+
+  async fn is_initialized(&self) -> Result<bool, MigrationError> {
+    let layout = self
+      .version_table_layout(&self.tables.schema, &self.tables.version_table)
+      .await?;
+
+    if layout != VersionTableLayout::Modules {
+      return Ok(false);
+    }
+
+    let tables = BOOKKEEPING_TABLES.map(String::from).to_vec();
+
+    Ok(
+      self
+        .db_client
+        .query_one(CHECK_TABLES_EXIST, &[&self.tables.schema, &tables])
+        .await?
+        .get(0),
+    )
+  }
+
+  async fn version_table_layout(
+    &self,
+    schema: &str,
+    table: &str,
+  ) -> Result<VersionTableLayout, MigrationError> {
+    let columns: Option<Vec<String>> = self
+      .db_client
+      .query_one(GET_TABLE_COLUMNS, &[&schema, &table])
+      .await?
+      .get(0);
+
+    Ok(VersionTableLayout::from_columns(columns))
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct DirtyMigration {
+  pub version: Version,
+  pub direction: MigrationDirection,
+  pub error: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlannedMigration {
+  pub version: Version,
+  pub source: Option<String>,
+  pub sql: Option<String>,
+  pub checksum: Option<String>,
+  pub transactional: bool,
+  pub baseline: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct MigrationPlan {
+  pub module_name: String,
+  pub schema: Option<String>,
+  pub current: Version,
+  pub migrations: Vec<PlannedMigration>,
+  pub repeatables: Vec<PlannedRepeatable>,
+  pub tables: MigratorTables,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlannedRepeatable {
+  pub name: String,
+  pub source: Option<String>,
+  pub sql: String,
+  pub checksum: String,
+}
+
+impl MigrationPlan {
+  pub fn is_empty(&self) -> bool {
+    self.migrations.is_empty() && self.repeatables.is_empty()
+  }
+
+  pub fn to_sql_script(&self) -> Result<String, MigrationError> {
+    use std::fmt::Write;
+
+    let module = quote_literal(&self.module_name);
+    let module_schema = quote_literal(self.schema.as_deref().unwrap_or(""));
+    let schema = quote_ident(&self.tables.schema);
+    let version_table = format!("{schema}.{}", quote_ident(&self.tables.version_table));
+    let mut script = format!(
+      "-- Migration plan for module {} from {}\n",
+      self.module_name, self.current
+    );
+
+    if let Some(schema) = &self.schema {
+      let _ = writeln!(script, "\nSET search_path TO {};", search_path(schema));
+    }
+
+    for migration in &self.migrations {
+      let sql = migration
+        .sql
+        .as_deref()
+        .ok_or(MigrationError::NotExportable(migration.version.clone()))?;
+      let Version {
+        major,
+        minor,
+        patch,
+        ..
+      } = migration.version;
+      let checksum = migration
+        .checksum
+        .as_deref()
+        .map(quote_literal)
+        .unwrap_or_else(|| String::from("NULL"));
+
+      let _ = write!(
+        script,
+        "\n-- {}{}\n",
+        migration.version,
+        migration
+          .source
+          .as_deref()
+          .map(|source| format!(" ({source})"))
+          .unwrap_or_default(),
+      );
+
+      // Non-transactional migrations run bare, only the bookkeeping is wrapped.
+      match migration.transactional {
+        true => {
+          let _ = write!(script, "BEGIN;\n\n{}\n", sql.trim());
+        }
+        false => {
+          for statement in split_statements(sql) {
+            let _ = writeln!(script, "{statement};");
+          }
+
+          let _ = writeln!(script, "\nBEGIN;");
+        }
+      }
+
+      let _ = writeln!(
+        script,
+        "\nINSERT INTO {version_table} (module, schema, major, minor, patch)\n\
+         VALUES ({module}, {module_schema}, {major}, {minor}, {patch})\n\
+         ON CONFLICT (module, schema) DO UPDATE SET major = {major}, minor = {minor}, patch = {patch};"
+      );
+
+      if migration.baseline {
+        let _ = writeln!(
+          script,
+          "\nINSERT INTO {schema}.baseline (module, schema, major, minor, patch, checksum)\n\
+           VALUES ({module}, {module_schema}, {major}, {minor}, {patch}, {checksum});"
+        );
+      } else if migration.checksum.is_some() {
+        let _ = writeln!(
+          script,
+          "\nINSERT INTO {schema}.checksum (module, schema, major, minor, patch, checksum)\n\
+           VALUES ({module}, {module_schema}, {major}, {minor}, {patch}, {checksum})\n\
+           ON CONFLICT (module, schema, major, minor, patch) DO UPDATE SET checksum = {checksum};"
+        );
+      }
+
+      let _ = writeln!(
+        script,
+        "\nINSERT INTO {schema}.history\n\
+         (module, schema, major, minor, patch, direction, duration_ms, checksum, success, application_name)\n\
+         VALUES ({module}, {module_schema}, {major}, {minor}, {patch}, 'up', 0, {checksum}, true, 'manual');\n\
+         \nCOMMIT;"
+      );
+    }
+
+    for repeatable in &self.repeatables {
+      let name = quote_literal(&repeatable.name);
+      let checksum = quote_literal(&repeatable.checksum);
+
+      let _ = write!(
+        script,
+        "\n-- Repeatable {}{}\nBEGIN;\n\n{}\n",
+        repeatable.name,
+        repeatable
+          .source
+          .as_deref()
+          .map(|source| format!(" ({source})"))
+          .unwrap_or_default(),
+        repeatable.sql.trim(),
+      );
+
+      let _ = writeln!(
+        script,
+        "\nINSERT INTO {schema}.repeatable (module, schema, name, checksum)\n\
+         VALUES ({module}, {module_schema}, {name}, {checksum})\n\
+         ON CONFLICT (module, schema, name) DO UPDATE SET checksum = {checksum}, applied_at = now();\n\
+         \nCOMMIT;"
+      );
+    }
+
+    Ok(script)
+  }
+}
+
+fn quote_literal(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "''"))
+}
+
+fn quote_ident(value: &str) -> String {
+  format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn search_path(schema: &str) -> String {
+  format!("{}, public", quote_ident(schema))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationDirection {
+  Up,
+  Down,
+}
+
+impl MigrationDirection {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      MigrationDirection::Up => "up",
+      MigrationDirection::Down => "down",
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct MigrationRecord {
+  pub module: String,
+  pub schema: Option<String>,
+  pub version: Version,
+  pub direction: MigrationDirection,
+  pub applied_at: SystemTime,
+  pub duration: Duration,
+  pub checksum: Option<String>,
+  pub success: bool,
+  pub error: Option<String>,
+  pub host: Option<String>,
+  pub application_name: Option<String>,
+}
+
+fn hostname() -> Option<String> {
+  nix::unistd::gethostname()
+    .ok()
+    .and_then(|host| host.into_string().ok())
+}
+
+/// The bookkeeping tables store major, minor and patch only, so a pre-release would be recorded
+/// as its release and the release itself then skipped.
+fn check_version(version: &Version) -> Result<(), MigrationError> {
+  match version.pre.is_some() || version.build.is_some() {
+    true => Err(MigrationError::UnsupportedVersion(version.clone())),
+    false => Ok(()),
+  }
+}
+
+fn is_lock_timeout(err: &MigrationError) -> bool {
+  match err {
+    MigrationError::Postgres(err) => err.code() == Some(&SqlState::LOCK_NOT_AVAILABLE),
+    _ => false,
+  }
+}
+
+fn describe_error(err: &MigrationError) -> String {
+  match err {
+    MigrationError::Postgres(err) => fmt_pg_error(err),
+    err => err.to_string(),
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationInitError {
+  #[error(transparent)]
+  DatabaseConfigError(#[from] DatabaseConfigError),
+  #[error(transparent)]
+  DeadpoolPoolError(#[from] DeadpoolPoolError),
+  #[error(transparent)]
+  MigrationError(#[from] MigrationError),
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+  #[error("Could not initialize version table")]
+  CouldNotInitializeVersionTable,
+  #[error("Base version does not support modules, please upgrade")]
+  NoModules,
+  #[error("Applied migrations have changed since they were run: {}", .0.iter().join(", "))]
+  ChecksumMismatch(Vec<Version>),
+  #[error("Timed out after {1:?} waiting for the migration lock of module {0}")]
+  LockTimeout(String, Duration),
+  #[error("Module {0} is dirty after a failed non-transactional migration {1}, resolve it first")]
+  Dirty(String, Version),
+  #[error("Migration {0} does not support running outside of a transaction")]
+  TransactionRequired(Version),
+  #[error("Migration {0} has no SQL and cannot be exported")]
+  NotExportable(Version),
+  #[error("{module} {version} requires {requires} >= {required}, but it is at {current}")]
+  UnsatisfiedRequirement {
+    module: String,
+    version: Version,
+    requires: String,
+    required: Version,
+    current: Version,
+  },
+  #[error("Invalid migration directive: {0}")]
+  InvalidDirective(String),
+  #[error("Migration {0} cannot be reverted")]
+  Irreversible(Version),
+  #[error("Migration {0} has pre-release or build metadata, which cannot be recorded")]
+  UnsupportedVersion(Version),
+  #[error("No migration for version {0}")]
+  UnknownVersion(Version),
+  #[error("Schema {0} does not exist")]
+  UnknownSchema(String),
+  #[error("{module} is at {current}, older than the {required} this build requires")]
+  SchemaTooOld {
+    module: String,
+    current: Version,
+    required: Version,
+  },
+  #[error("{module} is at {current}, newer than the {supported} this build supports")]
+  SchemaTooNew {
+    module: String,
+    current: Version,
+    supported: Version,
+  },
+  #[error(
+    "{module} {version} gave up waiting for a lock after {attempts} attempts, blocked on {} by pid {}",
+    relation.as_deref().unwrap_or("an unknown relation"),
+    pid.map(|pid| pid.to_string()).unwrap_or_else(|| String::from("unknown"))
+  )]
+  Blocked {
+    module: String,
+    version: Version,
+    relation: Option<String>,
+    pid: Option<i32>,
+    attempts: u32,
+  },
+  #[error("Migrations failed lint checks: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+  Lint(Vec<LintFinding>),
+  #[error("Interactive required.")]
+  InteractiveRequired,
+  #[error(transparent)]
+  IoError(#[from] std::io::Error),
+  #[error("Error: {0}")]
+  OtherError(String),
+  #[error(transparent)]
+  Postgres(#[from] tokio_postgres::Error),
+}
+
+#[async_trait]
+pub trait Migration: Send + Sync {
+  fn version(&self) -> Version;
+
+  fn checksum(&self) -> Option<String> {
+    None
+  }
+
+  fn requires(&self) -> Vec<ModuleVersion> {
+    vec![]
+  }
+
+  fn source(&self) -> Option<&str> {
+    None
+  }
+
+  fn sql(&self) -> Option<&str> {
+    None
+  }
+
+  fn timeouts(&self) -> MigrationTimeouts {
+    MigrationTimeouts::default()
+  }
+
+  async fn do_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError>;
+
+  fn is_reversible(&self) -> bool {
+    false
+  }
+
+  async fn revert_migration(
+    &self,
+    _conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    Err(MigrationError::Irreversible(self.version()))
+  }
+
+  /// Whether the migration runs inside a transaction. When `false`, the `*_no_transaction`
+  /// variants are called instead, for statements such as `CREATE INDEX CONCURRENTLY`.
+  fn is_transactional(&self) -> bool {
+    true
+  }
+
+  fn is_revert_transactional(&self) -> bool {
+    self.is_transactional()
+  }
+
+  async fn do_migration_no_transaction(
+    &self,
+    _conn: &tokio_postgres::Client,
+  ) -> Result<(), MigrationError> {
+    Err(MigrationError::TransactionRequired(self.version()))
+  }
+
+  async fn revert_migration_no_transaction(
+    &self,
+    _conn: &tokio_postgres::Client,
+  ) -> Result<(), MigrationError> {
+    Err(MigrationError::Irreversible(self.version()))
+  }
+
+  fn as_batched(&self) -> Option<&dyn BatchedMigration> {
+    None
+  }
+}
+
+#[async_trait]
+impl<T: Migration + ?Sized> Migration for &T {
+  fn version(&self) -> Version {
+    (**self).version()
+  }
+
+  fn checksum(&self) -> Option<String> {
+    (**self).checksum()
+  }
+
+  fn requires(&self) -> Vec<ModuleVersion> {
+    (**self).requires()
+  }
+
+  fn source(&self) -> Option<&str> {
+    (**self).source()
+  }
+
+  fn sql(&self) -> Option<&str> {
+    (**self).sql()
+  }
+
+  fn timeouts(&self) -> MigrationTimeouts {
+    (**self).timeouts()
+  }
+
+  async fn do_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    (**self).do_migration(conn).await
+  }
+
+  fn is_reversible(&self) -> bool {
+    (**self).is_reversible()
+  }
+
+  async fn revert_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    (**self).revert_migration(conn).await
+  }
+
+  fn is_transactional(&self) -> bool {
+    (**self).is_transactional()
+  }
+
+  fn is_revert_transactional(&self) -> bool {
+    (**self).is_revert_transactional()
+  }
+
+  async fn do_migration_no_transaction(
+    &self,
+    conn: &tokio_postgres::Client,
+  ) -> Result<(), MigrationError> {
+    (**self).do_migration_no_transaction(conn).await
+  }
+
+  async fn revert_migration_no_transaction(
+    &self,
+    conn: &tokio_postgres::Client,
+  ) -> Result<(), MigrationError> {
+    (**self).revert_migration_no_transaction(conn).await
+  }
+
+  fn as_batched(&self) -> Option<&dyn BatchedMigration> {
+    (**self).as_batched()
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Batch {
+  pub rows: u64,
+  pub cursor: Option<String>,
+}
+
+/// Each batch is committed together with its cursor, so an interrupted run resumes after the
+/// last committed batch. Registered wrapped in a `DataMigration`.
+#[async_trait]
+pub trait BatchedMigration: Send + Sync {
+  fn version(&self) -> Version;
+
+  fn batch_size(&self) -> u64 {
+    DEFAULT_BATCH_SIZE
+  }
+
+  async fn total(&self, _conn: &tokio_postgres::Client) -> Result<Option<u64>, MigrationError> {
+    Ok(None)
+  }
+
+  async fn run_batch(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+    cursor: Option<&str>,
+    batch_size: u64,
+  ) -> Result<Batch, MigrationError>;
+}
+
+pub struct DataMigration<T>(pub T);
+
+#[async_trait]
+impl<T: BatchedMigration> Migration for DataMigration<T> {
+  fn version(&self) -> Version {
+    self.0.version()
+  }
+
+  async fn do_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    let mut cursor = None;
+
+    loop {
+      let batch = self
+        .0
+        .run_batch(conn, cursor.as_deref(), self.0.batch_size())
+        .await?;
+
+      match batch.cursor {
+        Some(next) => cursor = Some(next),
+        None => return Ok(()),
+      }
+    }
+  }
+
+  fn is_transactional(&self) -> bool {
+    false
+  }
+
+  fn as_batched(&self) -> Option<&dyn BatchedMigration> {
+    Some(&self.0)
+  }
+}
+
+fn parse_requires_directives(query: &str) -> Result<Vec<ModuleVersion>, MigrationError> {
+  let directives =
+    parse_directives(query).map_err(|line| MigrationError::InvalidDirective(line.into()))?;
+
+  Ok(
+    directives
+      .into_iter()
+      .filter_map(|directive| match directive {
+        Directive::Requires(module, (major, minor, patch)) => {
+          Some(ModuleVersion::new(module, major, minor, patch))
+        }
+        _ => None,
+      })
+      .collect(),
+  )
+}
+
+fn parse_timeout_directives(query: &str) -> Result<MigrationTimeouts, MigrationError> {
+  let directives =
+    parse_directives(query).map_err(|line| MigrationError::InvalidDirective(line.into()))?;
+  let mut timeouts = MigrationTimeouts::default();
+
+  for directive in directives {
+    match directive {
+      Directive::LockTimeout(timeout) => timeouts.lock_timeout = Some(timeout),
+      Directive::StatementTimeout(timeout) => timeouts.statement_timeout = Some(timeout),
+      Directive::Requires(..) => {}
+    }
+  }
+
+  Ok(timeouts)
+}
+
+pub fn checksum(content: &str) -> String {
+  Sha256::digest(content.as_bytes())
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+pub struct RepeatableMigration {
+  name: &'static str,
+  query: &'static str,
+  checksum: String,
+  source: Option<&'static str>,
+}
+
+impl RepeatableMigration {
+  pub fn new(name: &'static str, query: &'static str) -> Self {
+    Self {
+      name,
+      query,
+      checksum: checksum(query),
+      source: None,
+    }
+  }
+
+  pub fn with_source(mut self, source: &'static str) -> Self {
+    self.source = Some(source);
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    self.name
+  }
+}
+
+pub struct PlainMigration {
+  version: Version,
+  query: &'static str,
+  down: Option<&'static str>,
+  checksum: String,
+  source: Option<&'static str>,
+  transactional: bool,
+  down_transactional: bool,
+  requires: Vec<ModuleVersion>,
+  timeouts: MigrationTimeouts,
+}
+
+impl PlainMigration {
+  pub fn new(version: Version, query: &'static str) -> Self {
+    Self::try_new(version.clone(), query).unwrap_or_else(|err| panic!("{version}: {err}"))
+  }
+
+  pub fn try_new(version: Version, query: &'static str) -> Result<Self, MigrationError> {
+    let (query, down) = split_down_section(query);
+    let transactional = !has_no_transaction_directive(query);
+    let down_transactional = !down.is_some_and(has_no_transaction_directive);
+    // Only the up section, so that fixing a down script does not count as drift.
+    let checksum = checksum(query);
+    let requires = parse_requires_directives(query)?;
+    let timeouts = parse_timeout_directives(query)?;
+
+    Ok(Self {
+      version,
+      query,
+      down,
+      checksum,
+      source: None,
+      transactional,
+      down_transactional,
+      requires,
+      timeouts,
+    })
+  }
+
+  pub fn with_source(mut self, source: &'static str) -> Self {
+    self.source = Some(source);
+    self
+  }
+}
+
+#[async_trait]
+impl Migration for PlainMigration {
+  fn version(&self) -> Version {
+    self.version.clone()
+  }
+
+  fn checksum(&self) -> Option<String> {
+    Some(self.checksum.clone())
+  }
+
+  fn requires(&self) -> Vec<ModuleVersion> {
+    self.requires.clone()
+  }
+
+  fn source(&self) -> Option<&str> {
+    self.source
+  }
+
+  fn sql(&self) -> Option<&str> {
+    Some(self.query)
+  }
+
+  fn timeouts(&self) -> MigrationTimeouts {
+    self.timeouts
+  }
+
+  async fn do_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    conn.batch_execute(self.query).await?;
+
+    Ok(())
+  }
+
+  fn is_reversible(&self) -> bool {
+    self.down.is_some()
+  }
+
+  async fn revert_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    match self.down {
+      Some(down) => conn.batch_execute(down).await?,
+      None => return Err(MigrationError::Irreversible(self.version.clone())),
+    }
+
+    Ok(())
+  }
+
+  fn is_transactional(&self) -> bool {
+    self.transactional
+  }
+
+  fn is_revert_transactional(&self) -> bool {
+    self.down_transactional
+  }
+
+  async fn do_migration_no_transaction(
+    &self,
+    conn: &tokio_postgres::Client,
+  ) -> Result<(), MigrationError> {
+    // Statements are sent one at a time, a multi-statement query runs as a single transaction.
+    for statement in split_statements(self.query) {
+      conn.batch_execute(statement).await?;
+    }
+
+    Ok(())
+  }
+
+  async fn revert_migration_no_transaction(
+    &self,
+    conn: &tokio_postgres::Client,
+  ) -> Result<(), MigrationError> {
+    let down = self
+      .down
+      .ok_or(MigrationError::Irreversible(self.version.clone()))?;
+
+    for statement in split_statements(down) {
+      conn.batch_execute(statement).await?;
+    }
+
+    Ok(())
+  }
+}
+
+const GET_TABLE_COLUMNS: &str = r#"
+
+SELECT
+  array_agg(column_name::text ORDER BY column_name::text)
+FROM information_schema.columns
+WHERE
+  table_schema = $1
+  AND table_name = $2
+
+"#;
+
+const BOOKKEEPING_TABLES: [&str; 7] = [
+  "checksum",
+  "history",
+  "dirty",
+  "baseline",
+  "repeatable",
+  "cursor",
+  "seed",
+];
+
+const CHECK_TABLES_EXIST: &str = r#"
+
+SELECT
+  bool_and(to_regclass(format('%I.%I', $1::text, name)) IS NOT NULL)
+FROM unnest($2::text[]) AS name
+
+"#;
+
+const MOVE_LEGACY_VERSION: &str = r#"
+
+ALTER TABLE public.version SET SCHEMA {schema};
+
+"#;
+
+const RENAME_LEGACY_VERSION: &str = r#"
+
+ALTER TABLE {schema}.version RENAME TO {version_table};
+
+"#;
+
+const GET_VERSION_MODULE: &str = r#"
+
+SELECT
+  major, minor, patch
+FROM
+  {schema}.{version_table}
+WHERE
+  module = $1
+  AND schema = $2
+
+"#;
+
+const GET_VERSION_NO_SCHEMA: &str = r#"
+
+SELECT
+  major, minor, patch
+FROM
+  {schema}.{version_table}
+WHERE
+  module = $1
+
+"#;
+
+const GET_VERSION_NO_MODULE: &str = r#"
+
+SELECT
+  major, minor, patch
+FROM
+  {schema}.{version_table}
+
+"#;
+
+const UPDATE_MODULE_VERSION: &str = r#"
+
+INSERT INTO {schema}.{version_table}
+(module, schema, major, minor, patch)
+VALUES
+($1, $2, $3, $4, $5)
+ON CONFLICT (module, schema)
+DO UPDATE SET major = $3, minor = $4, patch = $5;
+
+"#;
+
+const ADD_VERSION_MODULE_COLUMN: &str = r#"
+
+ALTER TABLE {schema}.{version_table}
+  ADD COLUMN module VARCHAR(128);
+
+UPDATE {schema}.{version_table}
+SET module = 'fuzion';
+
+ALTER TABLE {schema}.{version_table}
+  ALTER COLUMN module SET NOT NULL;
+
+ALTER TABLE {schema}.{version_table}
+  ADD PRIMARY KEY (module);
+
+"#;
+
+const ADD_VERSION_SCHEMA_COLUMN: &str = r#"
+
+ALTER TABLE {schema}.{version_table}
+  ADD COLUMN schema VARCHAR(128) NOT NULL DEFAULT '';
+
+"#;
+
+const DROP_VERSION_CONSTRAINT: &str = "ALTER TABLE {schema}.{version_table} DROP CONSTRAINT";
+
+const ADD_VERSION_PRIMARY_KEY: &str = r#"
+
+ALTER TABLE {schema}.{version_table}
+  ADD PRIMARY KEY (module, schema);
+
+"#;
+
+const GET_PRIMARY_KEYS: &str = r#"
+
+SELECT
+  conname::text
+FROM
+  pg_constraint
+WHERE
+  conrelid = format('%I.%I', $1::text, $2::text)::regclass
+  AND contype = 'p'
+
+"#;
+
+const CREATE_MIGRATOR_SCHEMA: &str = r#"
+
+CREATE SCHEMA IF NOT EXISTS {schema};
+
+"#;
+
+const CREATE_VERSION_TABLE: &str = r#"
+
+CREATE TABLE {schema}.{version_table} (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    major smallint NOT NULL,
+    minor smallint NOT NULL,
+    patch smallint NOT NULL,
+    PRIMARY KEY (module, schema)
+);
+
+"#;
+
+const CREATE_CHECKSUM_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.checksum (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    major smallint NOT NULL,
+    minor smallint NOT NULL,
+    patch smallint NOT NULL,
+    checksum varchar(128) NOT NULL,
+    PRIMARY KEY (module, schema, major, minor, patch)
+);
+
+"#;
+
+const GET_MODULE_CHECKSUMS: &str = r#"
+
+SELECT
+  major, minor, patch, checksum
+FROM
+  {schema}.checksum
+WHERE
+  module = $1
+  AND schema = $2
+
+"#;
+
+const UPDATE_MODULE_CHECKSUM: &str = r#"
+
+INSERT INTO {schema}.checksum
+(module, schema, major, minor, patch, checksum)
+VALUES
+($1, $2, $3, $4, $5, $6)
+ON CONFLICT (module, schema, major, minor, patch)
+DO UPDATE SET checksum = $6;
+
+"#;
+
+const DELETE_MODULE_CHECKSUM: &str = r#"
+
+DELETE FROM {schema}.checksum
+WHERE
+  module = $1
+  AND schema = $2
+  AND major = $3
+  AND minor = $4
+  AND patch = $5;
+
+"#;
+
+const CREATE_HISTORY_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.history (
+    id bigserial PRIMARY KEY,
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    major smallint NOT NULL,
+    minor smallint NOT NULL,
+    patch smallint NOT NULL,
+    direction varchar(8) NOT NULL,
+    applied_at timestamptz NOT NULL DEFAULT now(),
+    duration_ms bigint NOT NULL,
+    checksum varchar(128),
+    success boolean NOT NULL,
+    error text,
+    host varchar(255),
+    application_name varchar(255)
+);
+
+CREATE INDEX IF NOT EXISTS history_module_idx ON {schema}.history (module, schema);
+
+"#;
+
+const INSERT_HISTORY: &str = r#"
+
+INSERT INTO {schema}.history
+(module, schema, major, minor, patch, direction, duration_ms, checksum, success, error, host,
+ application_name)
+VALUES
+($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NULLIF(current_setting('application_name'), ''));
+
+"#;
+
+const GET_MODULE_HISTORY: &str = r#"
+
+SELECT
+  module, NULLIF(schema, ''), major, minor, patch, direction, applied_at, duration_ms, checksum,
+  success, error, host, application_name
+FROM
+  {schema}.history
+WHERE
+  module = $1
+  AND schema = $2
+ORDER BY
+  applied_at, id
+
+"#;
+
+const TRY_ADVISORY_LOCK: &str = r#"
+
+SELECT pg_try_advisory_lock(hashtextextended(concat_ws('@', 'migrator:' || $1, NULLIF($2::text, '')), 0));
+
+"#;
+
+const ADVISORY_UNLOCK: &str = r#"
+
+SELECT pg_advisory_unlock(hashtextextended(concat_ws('@', 'migrator:' || $1, NULLIF($2::text, '')), 0));
+
+"#;
+
+const ADVISORY_UNLOCK_ALL: &str = r#"
+
+SELECT pg_advisory_unlock_all();
+
+"#;
+
+const INITIALIZE_ADVISORY_LOCK: &str = r#"
+
+SELECT pg_advisory_lock(hashtextextended('migrator', 0));
+
+"#;
+
+const INITIALIZE_ADVISORY_UNLOCK: &str = r#"
+
+SELECT pg_advisory_unlock(hashtextextended('migrator', 0));
+
+"#;
+
+const CREATE_DIRTY_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.dirty (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    major smallint NOT NULL,
+    minor smallint NOT NULL,
+    patch smallint NOT NULL,
+    direction varchar(8) NOT NULL,
+    target_major smallint NOT NULL,
+    target_minor smallint NOT NULL,
+    target_patch smallint NOT NULL,
+    error text,
+    marked_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (module, schema)
+);
+
+"#;
+
+const MARK_MODULE_DIRTY: &str = r#"
+
+INSERT INTO {schema}.dirty
+(module, schema, major, minor, patch, direction, target_major, target_minor, target_patch)
+VALUES
+($1, $2, $3, $4, $5, $6, $7, $8, $9);
+
+"#;
+
+const UPDATE_DIRTY_ERROR: &str = r#"
+
+UPDATE {schema}.dirty
+SET error = $3
+WHERE
+  module = $1
+  AND schema = $2;
+
+"#;
+
+const GET_MODULE_DIRTY: &str = r#"
+
+SELECT
+  major, minor, patch, direction, error, target_major, target_minor, target_patch
+FROM
+  {schema}.dirty
+WHERE
+  module = $1
+  AND schema = $2
+
+"#;
+
+const CLEAR_MODULE_DIRTY: &str = r#"
+
+DELETE FROM {schema}.dirty
+WHERE
+  module = $1
+  AND schema = $2;
+
+"#;
+
+const CREATE_BASELINE_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.baseline (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    major smallint NOT NULL,
+    minor smallint NOT NULL,
+    patch smallint NOT NULL,
+    checksum varchar(128),
+    applied_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (module, schema)
+);
+
+"#;
+
+const INSERT_MODULE_BASELINE: &str = r#"
+
+INSERT INTO {schema}.baseline
+(module, schema, major, minor, patch, checksum)
+VALUES
+($1, $2, $3, $4, $5, $6);
+
+"#;
+
+const GET_MODULE_BASELINE: &str = r#"
+
+SELECT
+  major, minor, patch, checksum
+FROM
+  {schema}.baseline
+WHERE
+  module = $1
+  AND schema = $2
+
+"#;
+
+const CREATE_REPEATABLE_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.repeatable (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    name varchar(255) NOT NULL,
+    checksum varchar(128) NOT NULL,
+    applied_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (module, schema, name)
+);
+
+"#;
+
+const GET_MODULE_REPEATABLES: &str = r#"
+
+SELECT
+  name, checksum
+FROM
+  {schema}.repeatable
+WHERE
+  module = $1
+  AND schema = $2
+
+"#;
+
+const UPDATE_MODULE_REPEATABLE: &str = r#"
+
+INSERT INTO {schema}.repeatable
+(module, schema, name, checksum)
+VALUES
+($1, $2, $3, $4)
+ON CONFLICT (module, schema, name)
+DO UPDATE SET checksum = $4, applied_at = now();
+
+"#;
+
+const CREATE_SEED_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.seed (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    name varchar(255) NOT NULL,
+    checksum varchar(128) NOT NULL,
+    applied_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (module, schema, name)
+);
+
+"#;
+
+const GET_MODULE_SEEDS: &str = r#"
+
+SELECT
+  name, checksum
+FROM
+  {schema}.seed
+WHERE
+  module = $1
+  AND schema = $2
+
+"#;
+
+const UPDATE_MODULE_SEED: &str = r#"
+
+INSERT INTO {schema}.seed
+(module, schema, name, checksum)
+VALUES
+($1, $2, $3, $4)
+ON CONFLICT (module, schema, name)
+DO UPDATE SET checksum = $4, applied_at = now();
+
+"#;
+
+const CREATE_CURSOR_TABLE: &str = r#"
+
+CREATE TABLE IF NOT EXISTS {schema}.cursor (
+    module varchar(128) NOT NULL,
+    schema varchar(128) NOT NULL DEFAULT '',
+    major smallint NOT NULL,
+    minor smallint NOT NULL,
+    patch smallint NOT NULL,
+    cursor text NOT NULL,
+    rows_done bigint NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (module, schema, major, minor, patch)
+);
+
+"#;
+
+const GET_MODULE_CURSOR: &str = r#"
+
+SELECT
+  cursor, rows_done
+FROM
+  {schema}.cursor
+WHERE
+  module = $1
+  AND schema = $2
+  AND major = $3
+  AND minor = $4
+  AND patch = $5
+
+"#;
+
+const UPDATE_MODULE_CURSOR: &str = r#"
+
+INSERT INTO {schema}.cursor
+(module, schema, major, minor, patch, cursor, rows_done)
+VALUES
+($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT (module, schema, major, minor, patch)
+DO UPDATE SET cursor = $6, rows_done = $7, updated_at = now();
+
+"#;
+
+const DELETE_MODULE_CURSOR: &str = r#"
+
+DELETE FROM {schema}.cursor
+WHERE
+  module = $1
+  AND schema = $2
+  AND major = $3
+  AND minor = $4
+  AND patch = $5;
+
+"#;
+
+const SET_CONFIG: &str = r#"
+
+SELECT set_config($1, $2, $3);
+
+"#;
+
+const GET_BLOCKING_LOCK: &str = r#"
+
+SELECT
+  format('%I.%I', n.nspname, c.relname), l.pid
+FROM pg_locks l
+JOIN pg_class c ON c.oid = l.relation
+JOIN pg_namespace n ON n.oid = c.relnamespace
+JOIN pg_stat_activity a ON a.pid = l.pid
+WHERE
+  l.granted
+  AND l.pid <> pg_backend_pid()
+  AND l.database = (SELECT oid FROM pg_database WHERE datname = current_database())
+  AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+  AND ($1::text[] IS NULL OR c.relname = ANY($1))
+ORDER BY
+  a.xact_start
+LIMIT 1
+
+"#;
+
+const CHECK_SCHEMA_EXISTS: &str = r#"
+
+SELECT EXISTS (
+  SELECT
+    1
+  FROM pg_namespace
+  WHERE
+    nspname = $1
+);
+
+"#;
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    check_version, parse_requires_directives, parse_timeout_directives, Migration, MigrationError,
+    MigrationPlan, MigrationTimeouts, MigratorTables, PlainMigration, PlannedMigration,
+    PlannedRepeatable, VersionTableLayout,
+  };
+  use std::time::Duration;
+
+  use crate::version::{ModuleVersion, Version};
+
+  #[test]
+  fn version_from_filename() {
+    let cases = [
+      ("v1_2_3.sql", Some(Version::new(1, 2, 3))),
+      ("v0_10_0.sql", Some(Version::new(0, 10, 0))),
+      ("migrations/v1_0_0.sql", Some(Version::new(1, 0, 0))),
+      ("/srv/migrations/v1_0_0.sql", Some(Version::new(1, 0, 0))),
+      ("1_2_3.sql", None),
+      ("v1_2.sql", None),
+      ("v1_2_3_4.sql", None),
+      ("v1_2_x.sql", None),
+      ("v1__3.sql", None),
+      ("v1_2_99999.sql", None),
+      ("v1_0_0xsql", None),
+      ("v1_0_0.sql.bak", None),
+      ("xv1_0_0.sql", None),
+    ];
+
+    for (filename, version) in cases {
+      assert_eq!(Version::from_filename(filename), version, "{filename}");
+    }
+  }
+
+  fn columns(columns: &[&str]) -> Option<Vec<String>> {
+    Some(columns.iter().map(|e| e.to_string()).collect())
+  }
+
+  #[test]
+  fn check_version_rejects_pre_release_and_build() {
+    assert!(check_version(&Version::new(1, 0, 0)).is_ok());
+
+    for version in [
+      Version::new(1, 0, 0).with_pre("rc.1"),
+      Version::new(1, 0, 0).with_build("abc"),
+    ] {
+      assert!(matches!(
+        check_version(&version),
+        Err(MigrationError::UnsupportedVersion(ref v)) if *v == version
+      ));
+    }
+  }
+
+  #[test]
+  fn version_table_layout() {
+    let cases = [
+      (None, VersionTableLayout::Missing),
+      // public.version, before modules
+      (
+        columns(&["major", "minor", "patch"]),
+        VersionTableLayout::NoModules,
+      ),
+      (
+        columns(&["id", "patch", "minor", "major", "updated_at"]),
+        VersionTableLayout::NoModules,
+      ),
+      // migrator.version, before schemas
+      (
+        columns(&["major", "minor", "module", "patch"]),
+        VersionTableLayout::NoSchemas,
+      ),
+      (
+        columns(&["patch", "module", "note", "minor", "major"]),
+        VersionTableLayout::NoSchemas,
+      ),
+      (
+        columns(&["major", "minor", "module", "patch", "schema"]),
+        VersionTableLayout::Modules,
+      ),
+      (
+        columns(&["schema", "patch", "module", "minor", "major", "note"]),
+        VersionTableLayout::Modules,
+      ),
+      (
+        columns(&["module", "version"]),
+        VersionTableLayout::Unknown(vec!["module".into(), "version".into()]),
+      ),
+    ];
+
+    for (columns, layout) in cases {
+      assert_eq!(
+        VersionTableLayout::from_columns(columns.clone()),
+        layout,
+        "{columns:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn checksum_covers_up_section() {
+    let checksum = |query| {
+      PlainMigration::new(Version::new(1, 0, 0), query)
+        .checksum()
+        .unwrap()
+    };
+    let up = checksum("CREATE TABLE a ();\n");
+
+    assert_eq!(checksum("CREATE TABLE a ();\n-- down\nDROP TABLE a;\n"), up);
+    assert_eq!(
+      checksum("CREATE TABLE a ();\n-- down\nDROP TABLE IF EXISTS a;\n"),
+      up
+    );
+    assert_ne!(checksum("CREATE TABLE b ();\n-- down\nDROP TABLE a;\n"), up);
+  }
+
+  #[test]
+  fn no_transaction_directive_per_section() {
+    let cases = [
+      ("CREATE INDEX a_idx ON a (b);\n", (true, true)),
+      (
+        "-- migrator:no-transaction\nCREATE INDEX CONCURRENTLY a_idx ON a (b);\n-- down\nDROP INDEX a_idx;\n",
+        (false, true),
+      ),
+      (
+        "CREATE INDEX a_idx ON a (b);\n-- down\n-- migrator:no-transaction\nDROP INDEX CONCURRENTLY a_idx;\n",
+        (true, false),
+      ),
+    ];
+
+    for (query, transactional) in cases {
+      let migration = PlainMigration::new(Version::new(1, 0, 0), query);
+
+      assert_eq!(
+        (
+          migration.is_transactional(),
+          migration.is_revert_transactional()
+        ),
+        transactional,
+        "{query:?}"
+      );
+    }
+  }
+
+  fn planned(version: Version, sql: Option<&str>) -> PlannedMigration {
+    PlannedMigration {
+      version,
+      source: None,
+      sql: sql.map(String::from),
+      checksum: Some(String::from("abc")),
+      transactional: true,
+      baseline: false,
+    }
+  }
+
+  fn plan(migrations: Vec<PlannedMigration>) -> MigrationPlan {
+    MigrationPlan {
+      module_name: String::from("billing"),
+      schema: None,
+      current: Version::new(1, 0, 0),
+      migrations,
+      repeatables: vec![],
+      tables: MigratorTables::default(),
+    }
+  }
+
+  #[test]
+  fn to_sql_script() {
+    let mut migration = planned(Version::new(1, 1, 0), Some("CREATE TABLE a ();\n"));
+    migration.source = Some(String::from("v1_1_0.sql"));
+
+    assert_eq!(
+      plan(vec![migration]).to_sql_script().unwrap(),
+      r#"-- Migration plan for module billing from 1.0.0
+
+-- 1.1.0 (v1_1_0.sql)
+BEGIN;
+
+CREATE TABLE a ();
+
+INSERT INTO "migrator"."version" (module, schema, major, minor, patch)
+VALUES ('billing', '', 1, 1, 0)
+ON CONFLICT (module, schema) DO UPDATE SET major = 1, minor = 1, patch = 0;
+
+INSERT INTO "migrator".checksum (module, schema, major, minor, patch, checksum)
+VALUES ('billing', '', 1, 1, 0, 'abc')
+ON CONFLICT (module, schema, major, minor, patch) DO UPDATE SET checksum = 'abc';
+
+INSERT INTO "migrator".history
+(module, schema, major, minor, patch, direction, duration_ms, checksum, success, application_name)
+VALUES ('billing', '', 1, 1, 0, 'up', 0, 'abc', true, 'manual');
+
+COMMIT;
+"#
+    );
+  }
+
+  #[test]
+  fn to_sql_script_variants() {
+    let script = |plan: MigrationPlan| plan.to_sql_script().unwrap();
+
+    // Statements of a non-transactional migration run bare, before the bookkeeping transaction.
+    let mut migration = planned(
+      Version::new(1, 1, 0),
+      Some("CREATE INDEX CONCURRENTLY a_idx ON a (x);\nDROP INDEX CONCURRENTLY b_idx;"),
+    );
+    migration.transactional = false;
+    assert!(script(plan(vec![migration])).contains(
+      "\n-- 1.1.0\nCREATE INDEX CONCURRENTLY a_idx ON a (x);\nDROP INDEX CONCURRENTLY b_idx;\n\n\
+       BEGIN;\n\nINSERT INTO"
+    ));
+
+    let mut migration = planned(Version::new(1, 1, 0), Some("CREATE TABLE a ();"));
+    migration.baseline = true;
+    let baseline = script(plan(vec![migration]));
+    assert!(baseline.contains("INSERT INTO \"migrator\".baseline"));
+    assert!(!baseline.contains("INSERT INTO \"migrator\".checksum"));
+
+    let mut migration = planned(Version::new(1, 1, 0), Some("CREATE TABLE a ();"));
+    migration.checksum = None;
+    let unchecked = script(plan(vec![migration]));
+    assert!(!unchecked.contains("INSERT INTO \"migrator\".checksum"));
+    assert!(unchecked.contains("VALUES ('billing', '', 1, 1, 0, 'up', 0, NULL, true, 'manual')"));
+
+    let mut tenant = plan(vec![planned(Version::new(1, 1, 0), Some("SELECT 1;"))]);
+    tenant.module_name = String::from("it's");
+    tenant.schema = Some(String::from("tenant \"a\""));
+    tenant.tables = MigratorTables {
+      schema: String::from("ops"),
+      version_table: String::from("versions"),
+    };
+    let tenant = script(tenant);
+    assert!(tenant.contains("\nSET search_path TO \"tenant \"\"a\"\"\", public;\n"));
+    assert!(tenant.contains("INSERT INTO \"ops\".\"versions\""));
+    assert!(tenant.contains("VALUES ('it''s', 'tenant \"a\"', 1, 1, 0)"));
+
+    let mut repeatable = plan(vec![]);
+    repeatable.repeatables.push(PlannedRepeatable {
+      name: String::from("views"),
+      source: Some(String::from("views.sql")),
+      sql: String::from("CREATE OR REPLACE VIEW v AS SELECT 1;\n"),
+      checksum: String::from("def"),
+    });
+    assert!(script(repeatable).ends_with(
+      "\n-- Repeatable views (views.sql)\nBEGIN;\n\nCREATE OR REPLACE VIEW v AS SELECT 1;\n\n\
+       INSERT INTO \"migrator\".repeatable (module, schema, name, checksum)\n\
+       VALUES ('billing', '', 'views', 'def')\n\
+       ON CONFLICT (module, schema, name) DO UPDATE SET checksum = 'def', applied_at = now();\n\n\
+       COMMIT;\n"
+    ));
+
+    assert!(matches!(
+      plan(vec![planned(Version::new(1, 1, 0), None)]).to_sql_script(),
+      Err(MigrationError::NotExportable(ref version)) if *version == Version::new(1, 1, 0)
+    ));
+  }
+
+  #[test]
+  fn requires_directives() {
+    let cases = [
+      ("CREATE TABLE a ();", Some(vec![])),
+      (
+        "-- migrator:requires accounts >= 1.2.0\nCREATE TABLE a ();",
+        Some(vec![ModuleVersion::new("accounts", 1, 2, 0)]),
+      ),
+      (
+        "  -- migrator:requires accounts 1.2.0  \n-- migrator:requires fuzion >= 0.3.1\n",
+        Some(vec![
+          ModuleVersion::new("accounts", 1, 2, 0),
+          ModuleVersion::new("fuzion", 0, 3, 1),
+        ]),
+      ),
+      ("-- migrator:requires accounts", None),
+      ("-- migrator:requires accounts >=", None),
+      ("-- migrator:requires accounts > 1.2.0", None),
+      ("-- migrator:requires accounts >= 1.2", None),
+      ("-- migrator:requires accounts >= 1.2.0-rc.1", None),
+      ("-- migrator:requires accounts >= 1.2.0 extra", None),
+      // Not a directive without the space after the prefix.
+      ("-- migrator:requiresaccounts >= 1.2.0", Some(vec![])),
+    ];
+
+    for (query, requires) in cases {
+      match requires {
+        Some(requires) => assert_eq!(parse_requires_directives(query).unwrap(), requires),
+        None => assert!(
+          matches!(
+            parse_requires_directives(query),
+            Err(MigrationError::InvalidDirective(_))
+          ),
+          "{query:?}"
+        ),
+      }
+    }
+  }
+
+  #[test]
+  fn timeout_directives() {
+    let timeouts = |lock: Option<u64>, statement: Option<u64>| MigrationTimeouts {
+      lock_timeout: lock.map(Duration::from_millis),
+      statement_timeout: statement.map(Duration::from_millis),
+    };
+
+    let cases = [
+      ("CREATE TABLE a ();", Some(timeouts(None, None))),
+      (
+        "-- migrator:lock-timeout 500ms\nALTER TABLE a ADD COLUMN b int;",
+        Some(timeouts(Some(500), None)),
+      ),
+      (
+        "  -- migrator:statement-timeout 2min  \n-- migrator:lock-timeout 5s\n",
+        Some(timeouts(Some(5_000), Some(120_000))),
+      ),
+      (
+        "-- migrator:statement-timeout 1h",
+        Some(timeouts(None, Some(3_600_000))),
+      ),
+      // The last directive wins.
+      (
+        "-- migrator:lock-timeout 1s\n-- migrator:lock-timeout 2s",
+        Some(timeouts(Some(2_000), None)),
+      ),
+      // Without a value, the line is a plain comment.
+      ("-- migrator:lock-timeout", Some(timeouts(None, None))),
+      ("-- migrator:lock-timeout  ", Some(timeouts(None, None))),
+      ("-- migrator:lock-timeout 5", None),
+      ("-- migrator:lock-timeout 5 s", None),
+      ("-- migrator:lock-timeout 1.5s", None),
+      ("-- migrator:statement-timeout -1s", None),
+      ("-- migrator:statement-timeout 5d", None),
+      ("-- migrator:statement-timeout 99999999999999999999h", None),
+    ];
+
+    for (query, expected) in cases {
+      match expected {
+        Some(expected) => assert_eq!(parse_timeout_directives(query).unwrap(), expected),
+        None => assert!(
+          matches!(
+            parse_timeout_directives(query),
+            Err(MigrationError::InvalidDirective(_))
+          ),
+          "{query:?}"
+        ),
+      }
+    }
+  }
+}
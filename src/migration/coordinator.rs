@@ -0,0 +1,321 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::version::{ModuleVersion, Version};
+
+use super::{MigrationError, Migrator, ModuleKey, Step};
+
+/// The full order is resolved before anything is executed, so an unsatisfiable requirement fails
+/// without touching the database.
+pub struct MigrationCoordinator<'a> {
+  migrators: Vec<Migrator<'a>>,
+}
+
+impl<'a> MigrationCoordinator<'a> {
+  pub fn new(migrators: Vec<Migrator<'a>>) -> MigrationCoordinator<'a> {
+    MigrationCoordinator { migrators }
+  }
+
+  pub fn module(mut self, migrator: Migrator<'a>) -> Self {
+    self.migrators.push(migrator);
+    self
+  }
+
+  pub async fn migrate(&mut self) -> Result<(), MigrationError> {
+    // Lock in a stable order so two coordinators cannot deadlock each other.
+    let mut order: Vec<usize> = (0..self.migrators.len()).collect();
+    order.sort_by(|a, b| self.migrators[*a].module.cmp(&self.migrators[*b].module));
+
+    let mut locked = vec![];
+    let mut result = Ok(());
+
+    for idx in &order {
+      if let Err(err) = self.migrators[*idx].lock().await {
+        result = Err(err);
+        break;
+      }
+
+      locked.push(*idx);
+    }
+
+    if result.is_ok() {
+      result = self.migrate_locked().await;
+    }
+
+    for idx in locked.into_iter().rev() {
+      self.migrators[idx].unlock().await;
+    }
+
+    result
+  }
+
+  async fn migrate_locked(&mut self) -> Result<(), MigrationError> {
+    let mut versions: HashMap<ModuleKey, Version> = HashMap::new();
+
+    for migrator in &mut self.migrators {
+      migrator.check_versions()?;
+
+      let version = migrator.get_version().await?;
+
+      migrator.check_schema().await?;
+      migrator.check_dirty().await?;
+      migrator.verify_checksums(&version).await?;
+
+      versions.insert(migrator.module.clone(), version);
+    }
+
+    let steps = self.resolve(versions).await?;
+
+    for (idx, migrator) in self.migrators.iter().enumerate() {
+      let own: Vec<Step> = steps
+        .iter()
+        .filter(|(step_idx, _)| *step_idx == idx)
+        .map(|(_, step)| *step)
+        .collect();
+
+      migrator.check_lint(&own)?;
+    }
+
+    for (idx, step) in steps {
+      self.migrators[idx].apply_step(step).await?;
+    }
+
+    // Repeatable migrations and seeds may depend on any module's schema, so they run last.
+    for migrator in &mut self.migrators {
+      migrator.apply_repeatables().await?;
+    }
+
+    for migrator in &mut self.migrators {
+      migrator.apply_seeds().await?;
+    }
+
+    Ok(())
+  }
+
+  async fn resolve(
+    &self,
+    mut versions: HashMap<ModuleKey, Version>,
+  ) -> Result<Vec<(usize, Step)>, MigrationError> {
+    let steps: Vec<Vec<Step>> = self
+      .migrators
+      .iter()
+      .map(|migrator| migrator.pending_steps(&versions[&migrator.module], None))
+      .collect();
+
+    // Modules outside of this run can only be checked against what is already applied.
+    for migrator in &self.migrators {
+      for migration in migrator.migrations.iter().chain(&migrator.baseline) {
+        for requirement in migration.requires() {
+          if let Entry::Vacant(entry) = versions.entry(migrator.module.sibling(&requirement.0)) {
+            let version = migrator.get_module_version(entry.key()).await?;
+            entry.insert(version);
+          }
+        }
+      }
+    }
+
+    let modules: Vec<ModuleKey> = self
+      .migrators
+      .iter()
+      .map(|migrator| migrator.module.clone())
+      .collect();
+    let pending: Vec<Vec<PendingStep>> = self
+      .migrators
+      .iter()
+      .zip(&steps)
+      .map(|(migrator, steps)| {
+        steps
+          .iter()
+          .map(|step| {
+            let migration = migrator.step(*step);
+
+            (migration.version(), migration.requires())
+          })
+          .collect()
+      })
+      .collect();
+
+    let (idx, position) = match order_steps(&modules, &pending, &mut versions) {
+      Ok(order) => {
+        return Ok(
+          order
+            .into_iter()
+            .map(|(idx, position)| (idx, steps[idx][position]))
+            .collect(),
+        )
+      }
+      Err(blocked) => blocked,
+    };
+
+    let migrator = &self.migrators[idx];
+    let (version, requires) = &pending[idx][position];
+    let requirement = requires
+      .iter()
+      .find(|requirement| {
+        versions[&migrator.module.sibling(&requirement.0)] < requirement.as_version()
+      })
+      .unwrap();
+
+    error!(
+      "[{}] Cannot order migrations, {} requires {} >= {}",
+      migrator.module,
+      version,
+      requirement.0,
+      requirement.as_version()
+    );
+
+    Err(MigrationError::UnsatisfiedRequirement {
+      module: migrator.module.to_string(),
+      version: version.clone(),
+      requires: requirement.0.clone(),
+      required: requirement.as_version(),
+      current: versions[&migrator.module.sibling(&requirement.0)].clone(),
+    })
+  }
+}
+
+type PendingStep = (Version, Vec<ModuleVersion>);
+
+fn order_steps(
+  modules: &[ModuleKey],
+  pending: &[Vec<PendingStep>],
+  versions: &mut HashMap<ModuleKey, Version>,
+) -> Result<Vec<(usize, usize)>, (usize, usize)> {
+  let mut next = vec![0; modules.len()];
+  let mut order = vec![];
+
+  'progress: loop {
+    for (idx, module) in modules.iter().enumerate() {
+      let Some((version, requires)) = pending[idx].get(next[idx]) else {
+        continue;
+      };
+
+      let satisfied = requires
+        .iter()
+        .all(|requirement| versions[&module.sibling(&requirement.0)] >= requirement.as_version());
+
+      if satisfied {
+        versions.insert(module.clone(), version.clone());
+        order.push((idx, next[idx]));
+        next[idx] += 1;
+
+        continue 'progress;
+      }
+    }
+
+    break;
+  }
+
+  match (0..modules.len()).find(|idx| next[*idx] < pending[*idx].len()) {
+    Some(idx) => Err((idx, next[idx])),
+    None => Ok(order),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::{order_steps, PendingStep};
+  use crate::migration::ModuleKey;
+  use crate::version::{ModuleVersion, Version};
+
+  fn module(name: &str, schema: Option<&str>) -> ModuleKey {
+    ModuleKey {
+      name: name.into(),
+      schema: schema.map(String::from),
+    }
+  }
+
+  fn step(version: (i16, i16, i16), requires: &[(&str, (i16, i16, i16))]) -> PendingStep {
+    (
+      Version::new(version.0, version.1, version.2),
+      requires
+        .iter()
+        .map(|(module, (major, minor, patch))| ModuleVersion::new(module, *major, *minor, *patch))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn order_steps_by_requirements() {
+    let a = module("a", None);
+    let b = module("b", None);
+    let c = module("c", None);
+
+    let cases = [
+      (
+        vec![vec![step((1, 0, 0), &[]), step((1, 1, 0), &[])], vec![]],
+        Ok(vec![(0, 0), (0, 1)]),
+      ),
+      // b waits until a reached the version it requires.
+      (
+        vec![
+          vec![step((1, 0, 0), &[]), step((1, 1, 0), &[])],
+          vec![step((2, 0, 0), &[("a", (1, 1, 0))])],
+        ],
+        Ok(vec![(0, 0), (0, 1), (1, 0)]),
+      ),
+      // Steps interleave when the modules require each other's earlier versions.
+      (
+        vec![
+          vec![step((1, 0, 0), &[]), step((1, 1, 0), &[("b", (2, 0, 0))])],
+          vec![step((2, 0, 0), &[("a", (1, 0, 0))]), step((2, 1, 0), &[])],
+        ],
+        Ok(vec![(0, 0), (1, 0), (0, 1), (1, 1)]),
+      ),
+      // Requirements on modules outside of the run are checked against their current version.
+      (
+        vec![vec![step((1, 0, 0), &[("c", (0, 5, 0))])], vec![]],
+        Ok(vec![(0, 0)]),
+      ),
+      (
+        vec![
+          vec![step((1, 0, 0), &[]), step((1, 1, 0), &[("c", (1, 0, 0))])],
+          vec![],
+        ],
+        Err((0, 1)),
+      ),
+      (
+        vec![
+          vec![step((1, 0, 0), &[("b", (2, 0, 0))])],
+          vec![step((2, 0, 0), &[("a", (1, 0, 0))])],
+        ],
+        Err((0, 0)),
+      ),
+    ];
+
+    for (pending, order) in cases {
+      let mut versions = HashMap::from([
+        (a.clone(), Version::new(0, 0, 0)),
+        (b.clone(), Version::new(0, 0, 0)),
+        (c.clone(), Version::new(0, 5, 0)),
+      ]);
+
+      assert_eq!(
+        order_steps(&[a.clone(), b.clone()], &pending, &mut versions),
+        order,
+        "{pending:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn order_steps_within_schema() {
+    let modules = [module("a", Some("t1")), module("b", Some("t2"))];
+    let pending = vec![
+      vec![step((1, 0, 0), &[])],
+      vec![step((1, 0, 0), &[("a", (1, 0, 0))])],
+    ];
+    let mut versions = HashMap::from([
+      (module("a", Some("t1")), Version::new(0, 0, 0)),
+      (module("b", Some("t2")), Version::new(0, 0, 0)),
+      (module("a", Some("t2")), Version::new(0, 0, 0)),
+      (module("a", None), Version::new(1, 0, 0)),
+    ]);
+
+    // a in t1 does not satisfy b in t2, nor does a without a schema.
+    assert_eq!(order_steps(&modules, &pending, &mut versions), Err((1, 0)));
+    assert_eq!(versions[&modules[0]], Version::new(1, 0, 0));
+  }
+}
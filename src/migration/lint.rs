@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::version::Version;
+
+use super::sql::{identifier, split_statements, strip_comments, tokenize};
+use super::Migration;
+
+pub const ALLOW_DIRECTIVE: &str = "-- migrator:allow ";
+
+const VOLATILE_FUNCTIONS: &[&str] = &[
+  "clock_timestamp",
+  "gen_random_uuid",
+  "nextval",
+  "random",
+  "timeofday",
+  "txid_current",
+  "uuid_generate_v1",
+  "uuid_generate_v1mc",
+  "uuid_generate_v4",
+];
+
+const SERIAL_TYPES: &[&str] = &[
+  "bigserial",
+  "serial",
+  "serial2",
+  "serial4",
+  "serial8",
+  "smallserial",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintRule {
+  VolatileDefault,
+  IndexNotConcurrent,
+  AlterColumnType,
+  DropColumn,
+}
+
+impl LintRule {
+  pub const ALL: [LintRule; 4] = [
+    LintRule::VolatileDefault,
+    LintRule::IndexNotConcurrent,
+    LintRule::AlterColumnType,
+    LintRule::DropColumn,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      LintRule::VolatileDefault => "volatile-default",
+      LintRule::IndexNotConcurrent => "index-not-concurrent",
+      LintRule::AlterColumnType => "alter-column-type",
+      LintRule::DropColumn => "drop-column",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<LintRule> {
+    LintRule::ALL.into_iter().find(|rule| rule.name() == name)
+  }
+
+  fn message(&self) -> &'static str {
+    match self {
+      LintRule::VolatileDefault => {
+        "adding a column with a volatile default rewrites the table under an exclusive lock, add \
+         it without a default and backfill in batches"
+      }
+      LintRule::IndexNotConcurrent => {
+        "building an index without CONCURRENTLY blocks writes to the table, use CREATE INDEX \
+         CONCURRENTLY in a -- migrator:no-transaction migration"
+      }
+      LintRule::AlterColumnType => {
+        "changing a column type usually rewrites the table under an exclusive lock, add a new \
+         column and backfill it instead"
+      }
+      LintRule::DropColumn => {
+        "dropping a column breaks the previous release while it still runs, drop it in a later \
+         release once no code uses it"
+      }
+    }
+  }
+}
+
+impl fmt::Display for LintRule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+  Allow,
+  #[default]
+  Warn,
+  Deny,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LintPolicy {
+  #[serde(default)]
+  pub default: LintLevel,
+  #[serde(default)]
+  pub rules: HashMap<LintRule, LintLevel>,
+}
+
+impl LintPolicy {
+  pub fn new(default: LintLevel) -> LintPolicy {
+    LintPolicy {
+      default,
+      rules: HashMap::new(),
+    }
+  }
+
+  pub fn with_rule(mut self, rule: LintRule, level: LintLevel) -> Self {
+    self.rules.insert(rule, level);
+    self
+  }
+
+  pub fn level(&self, rule: LintRule) -> LintLevel {
+    self.rules.get(&rule).copied().unwrap_or(self.default)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct LintFinding {
+  pub rule: LintRule,
+  pub level: LintLevel,
+  pub version: Option<Version>,
+  pub source: Option<String>,
+  pub statement: String,
+}
+
+impl fmt::Display for LintFinding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(version) = &self.version {
+      write!(f, "{version} ")?;
+    }
+
+    if let Some(source) = &self.source {
+      write!(f, "({source}) ")?;
+    }
+
+    write!(
+      f,
+      "[{}] {}: {}",
+      self.rule,
+      self.rule.message(),
+      self.statement
+    )
+  }
+}
+
+pub fn lint_migration(migration: &dyn Migration, policy: &LintPolicy) -> Vec<LintFinding> {
+  let Some(sql) = migration.sql() else {
+    return vec![];
+  };
+
+  lint_sql(sql, policy)
+    .into_iter()
+    .map(|finding| LintFinding {
+      version: Some(migration.version()),
+      source: migration.source().map(String::from),
+      ..finding
+    })
+    .collect()
+}
+
+/// Lints a script. Tables created earlier in the same script are exempt, as nothing else can be
+/// using them yet.
+pub fn lint_sql(sql: &str, policy: &LintPolicy) -> Vec<LintFinding> {
+  let allowed = allowed_rules(sql);
+  let mut created = vec![];
+  let mut findings = vec![];
+
+  for statement in split_statements(sql) {
+    let tokens = tokenize(statement);
+
+    for rule in check_statement(&tokens, &mut created) {
+      let level = match allowed.as_ref() {
+        Some(allowed) if allowed.contains(&rule) => LintLevel::Allow,
+        None => LintLevel::Allow,
+        _ => policy.level(rule),
+      };
+
+      if level == LintLevel::Allow {
+        continue;
+      }
+
+      findings.push(LintFinding {
+        rule,
+        level,
+        version: None,
+        source: None,
+        statement: strip_comments(statement)
+          .split_whitespace()
+          .collect::<Vec<_>>()
+          .join(" "),
+      });
+    }
+  }
+
+  findings
+}
+
+fn allowed_rules(sql: &str) -> Option<Vec<LintRule>> {
+  let mut allowed = vec![];
+
+  for name in sql
+    .lines()
+    .filter_map(|line| line.trim().strip_prefix(ALLOW_DIRECTIVE))
+    .flat_map(|names| names.split([',', ' ']))
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+  {
+    match (name, LintRule::from_name(name)) {
+      ("all", _) => return None,
+      (_, Some(rule)) => allowed.push(rule),
+      (_, None) => warn!("Unknown lint rule in allow directive: {}", name),
+    }
+  }
+
+  Some(allowed)
+}
+
+fn is(token: Option<&&str>, keyword: &str) -> bool {
+  token.is_some_and(|token| token.eq_ignore_ascii_case(keyword))
+}
+
+fn check_statement(tokens: &[&str], created: &mut Vec<String>) -> Vec<LintRule> {
+  match (tokens.first(), tokens.get(1)) {
+    (Some(first), Some(_)) if first.eq_ignore_ascii_case("CREATE") => {
+      let modifiers = ["UNIQUE", "UNLOGGED", "TEMP", "TEMPORARY", "GLOBAL", "LOCAL"];
+      let kind = tokens[1..]
+        .iter()
+        .find(|token| !modifiers.iter().any(|m| token.eq_ignore_ascii_case(m)));
+
+      if is(kind, "TABLE") {
+        let name = tokens
+          .iter()
+          .skip_while(|token| !token.eq_ignore_ascii_case("TABLE"))
+          .skip(1)
+          .find(|token| {
+            !["IF", "NOT", "EXISTS"]
+              .iter()
+              .any(|k| token.eq_ignore_ascii_case(k))
+          });
+
+        if let Some(name) = name {
+          created.push(identifier(name));
+        }
+
+        return vec![];
+      }
+
+      if is(kind, "INDEX") {
+        let position = tokens
+          .iter()
+          .position(|token| token.eq_ignore_ascii_case("INDEX"))
+          .unwrap();
+
+        if is(tokens.get(position + 1), "CONCURRENTLY") {
+          return vec![];
+        }
+
+        let table = tokens
+          .iter()
+          .skip_while(|token| !token.eq_ignore_ascii_case("ON"))
+          .skip(1)
+          .find(|token| !token.eq_ignore_ascii_case("ONLY"));
+
+        return match table {
+          Some(table) if created.contains(&identifier(table)) => vec![],
+          _ => vec![LintRule::IndexNotConcurrent],
+        };
+      }
+
+      vec![]
+    }
+    (Some(first), Some(second))
+      if first.eq_ignore_ascii_case("ALTER") && second.eq_ignore_ascii_case("TABLE") =>
+    {
+      let position = skip(tokens, 2, &["IF", "EXISTS", "ONLY"]);
+
+      let Some(table) = tokens.get(position) else {
+        return vec![];
+      };
+
+      if created.contains(&identifier(table)) {
+        return vec![];
+      }
+
+      split_actions(&tokens[position + 1..])
+        .into_iter()
+        .filter_map(check_action)
+        .collect()
+    }
+    _ => vec![],
+  }
+}
+
+fn split_actions<'t>(tokens: &'t [&'t str]) -> Vec<&'t [&'t str]> {
+  let mut actions = vec![];
+  let mut depth = 0;
+  let mut start = 0;
+
+  for (idx, token) in tokens.iter().enumerate() {
+    match *token {
+      "(" => depth += 1,
+      ")" => depth -= 1,
+      "," | ";" if depth == 0 => {
+        actions.push(&tokens[start..idx]);
+        start = idx + 1;
+      }
+      _ => {}
+    }
+  }
+
+  actions.push(&tokens[start..]);
+  actions
+}
+
+fn skip(tokens: &[&str], mut position: usize, keywords: &[&str]) -> usize {
+  while keywords.iter().any(|k| is(tokens.get(position), k)) {
+    position += 1;
+  }
+
+  position
+}
+
+fn check_action(action: &[&str]) -> Option<LintRule> {
+  match action.first() {
+    first if is(first, "ADD") => {
+      let is_column = is(action.get(1), "COLUMN");
+      let constraints = [
+        "CONSTRAINT",
+        "PRIMARY",
+        "UNIQUE",
+        "FOREIGN",
+        "CHECK",
+        "EXCLUDE",
+      ];
+
+      if !is_column && constraints.iter().any(|k| is(action.get(1), k)) {
+        return None;
+      }
+
+      let position = skip(action, 1, &["COLUMN", "IF", "NOT", "EXISTS"]);
+
+      // Skip the column name to the type.
+      let column_type = action.get(position + 1);
+
+      if column_type.is_some_and(|t| SERIAL_TYPES.contains(&t.to_lowercase().as_str())) {
+        return Some(LintRule::VolatileDefault);
+      }
+
+      let rest = &action[(position + 1).min(action.len())..];
+
+      if rest
+        .iter()
+        .any(|token| token.eq_ignore_ascii_case("GENERATED"))
+      {
+        return Some(LintRule::VolatileDefault);
+      }
+
+      let default = rest
+        .iter()
+        .position(|token| token.eq_ignore_ascii_case("DEFAULT"))?;
+
+      rest[default..]
+        .windows(2)
+        .any(|pair| pair[1] == "(" && VOLATILE_FUNCTIONS.contains(&identifier(pair[0]).as_str()))
+        .then_some(LintRule::VolatileDefault)
+    }
+    first if is(first, "ALTER") => {
+      // Skip the column name.
+      let position = skip(action, 1, &["COLUMN"]) + 1;
+      let position = skip(action, position, &["SET", "DATA"]);
+
+      is(action.get(position), "TYPE").then_some(LintRule::AlterColumnType)
+    }
+    first if is(first, "DROP") => {
+      (!is(action.get(1), "CONSTRAINT")).then_some(LintRule::DropColumn)
+    }
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{lint_sql, LintLevel, LintPolicy, LintRule};
+
+  fn rules(sql: &str) -> Vec<LintRule> {
+    lint_sql(sql, &LintPolicy::default())
+      .into_iter()
+      .map(|finding| finding.rule)
+      .collect()
+  }
+
+  #[test]
+  fn flags_dangerous_statements() {
+    assert_eq!(
+      rules(
+        "ALTER TABLE a ADD COLUMN id uuid DEFAULT gen_random_uuid(), ADD b int DEFAULT 0;\n\
+         ALTER TABLE a ADD COLUMN n bigserial;\n\
+         CREATE UNIQUE INDEX a_b ON a (b);\n\
+         ALTER TABLE ONLY public.a ALTER COLUMN b SET DATA TYPE bigint, ALTER c TYPE text;\n\
+         ALTER TABLE a DROP COLUMN d, DROP CONSTRAINT a_pkey, DROP IF EXISTS e;"
+      ),
+      vec![
+        LintRule::VolatileDefault,
+        LintRule::VolatileDefault,
+        LintRule::IndexNotConcurrent,
+        LintRule::AlterColumnType,
+        LintRule::AlterColumnType,
+        LintRule::DropColumn,
+        LintRule::DropColumn,
+      ]
+    );
+  }
+
+  #[test]
+  fn allows_safe_statements() {
+    assert!(rules(
+      "CREATE INDEX CONCURRENTLY a_b ON a (b);\n\
+       ALTER TABLE a ADD COLUMN b timestamptz DEFAULT now(), ADD CONSTRAINT a_c CHECK (c > 0);\n\
+       ALTER TABLE a ALTER COLUMN b SET DEFAULT random();\n\
+       CREATE TABLE b (id serial);\n\
+       CREATE INDEX b_id ON b (id);\n\
+       ALTER TABLE b DROP COLUMN id;"
+    )
+    .is_empty());
+  }
+
+  #[test]
+  fn honours_allow_directives_and_policy() {
+    let sql = "-- migrator:allow drop-column\nALTER TABLE a DROP b;\nCREATE INDEX i ON a (c);";
+
+    assert_eq!(rules(sql), vec![LintRule::IndexNotConcurrent]);
+    assert!(rules(&format!("-- migrator:allow all\n{sql}")).is_empty());
+
+    let policy =
+      LintPolicy::new(LintLevel::Allow).with_rule(LintRule::IndexNotConcurrent, LintLevel::Deny);
+    let findings = lint_sql(
+      "CREATE INDEX i ON a (c); ALTER TABLE a ALTER b TYPE int;",
+      &policy,
+    );
+
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].level, LintLevel::Deny);
+  }
+}
@@ -0,0 +1,626 @@
+//! Expand/contract column changes. Each phase ships in its own release, and `contract` only once
+//! no deployed code uses the old column.
+
+use async_trait::async_trait;
+
+use crate::version::Version;
+
+use super::lint::ALLOW_DIRECTIVE;
+use super::{
+  checksum, quote_ident, Batch, BatchedMigration, DataMigration, Migration, MigrationError,
+};
+
+const DEFAULT_KEY: &str = "id";
+
+const DEFAULT_KEY_TYPE: &str = "bigint";
+
+const MAX_IDENTIFIER_LENGTH: usize = 63;
+
+pub struct RenameColumn {
+  sync: ColumnSync,
+}
+
+impl RenameColumn {
+  pub fn new(table: &str, column: &str, new_column: &str, column_type: &str) -> RenameColumn {
+    RenameColumn {
+      sync: ColumnSync::new(table, column, new_column, column_type, "{}", "{}"),
+    }
+  }
+
+  pub fn with_key(mut self, column: &str, column_type: &str) -> Self {
+    self.sync.key = column.into();
+    self.sync.key_type = column_type.into();
+    self
+  }
+
+  pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+    self.sync.batch_size = batch_size;
+    self
+  }
+
+  pub fn expand(&self, version: Version) -> GeneratedMigration {
+    self.sync.expand(version)
+  }
+
+  pub fn backfill(&self, version: Version) -> DataMigration<ColumnBackfill> {
+    self.sync.backfill(version)
+  }
+
+  pub fn contract(&self, version: Version) -> GeneratedMigration {
+    self.sync.contract(version, &self.sync.primary)
+  }
+}
+
+pub struct ChangeColumnType {
+  sync: ColumnSync,
+}
+
+impl ChangeColumnType {
+  pub fn new(table: &str, column: &str, old_type: &str, new_type: &str) -> ChangeColumnType {
+    ChangeColumnType {
+      sync: ColumnSync::new(
+        table,
+        column,
+        &format!("{column}_new"),
+        new_type,
+        &format!("{{}}::{new_type}"),
+        &format!("{{}}::{old_type}"),
+      ),
+    }
+  }
+
+  pub fn with_conversion(mut self, to_new: &str, to_old: &str) -> Self {
+    self.sync.to_shadow = to_new.into();
+    self.sync.to_primary = to_old.into();
+    self
+  }
+
+  pub fn with_key(mut self, column: &str, column_type: &str) -> Self {
+    self.sync.key = column.into();
+    self.sync.key_type = column_type.into();
+    self
+  }
+
+  pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+    self.sync.batch_size = batch_size;
+    self
+  }
+
+  pub fn expand(&self, version: Version) -> GeneratedMigration {
+    self.sync.expand(version)
+  }
+
+  pub fn backfill(&self, version: Version) -> DataMigration<ColumnBackfill> {
+    self.sync.backfill(version)
+  }
+
+  pub fn swap(&self, version: Version) -> GeneratedMigration {
+    let sync = &self.sync;
+    let table = quote_table(&sync.table);
+    let column = quote_ident(&sync.primary);
+    let new = quote_ident(&sync.shadow);
+    let old = quote_ident(&self.old_column());
+
+    let swapped = ColumnSync {
+      primary: sync.primary.clone(),
+      shadow: self.old_column(),
+      to_shadow: sync.to_primary.clone(),
+      to_primary: sync.to_shadow.clone(),
+      ..sync.clone()
+    };
+
+    let up = format!(
+      "{drop}\n\
+       ALTER TABLE {table} RENAME COLUMN {column} TO {old};\n\
+       ALTER TABLE {table} RENAME COLUMN {new} TO {column};\n\
+       {create}",
+      drop = sync.drop_trigger(),
+      create = swapped.create_trigger(),
+    );
+    let down = format!(
+      "{drop}\n\
+       ALTER TABLE {table} RENAME COLUMN {column} TO {new};\n\
+       ALTER TABLE {table} RENAME COLUMN {old} TO {column};\n\
+       {create}",
+      drop = swapped.drop_trigger(),
+      create = sync.create_trigger(),
+    );
+
+    GeneratedMigration::new(version, up, Some(down), sync.source("swap"))
+  }
+
+  pub fn contract(&self, version: Version) -> GeneratedMigration {
+    self.sync.contract(version, &self.old_column())
+  }
+
+  fn old_column(&self) -> String {
+    format!("{}_old", self.sync.primary)
+  }
+}
+
+/// Two columns kept in sync by a trigger. Writes to `primary` win over writes to `shadow`.
+#[derive(Clone)]
+struct ColumnSync {
+  table: String,
+  primary: String,
+  shadow: String,
+  shadow_type: String,
+  to_shadow: String,
+  to_primary: String,
+  key: String,
+  key_type: String,
+  batch_size: u64,
+}
+
+impl ColumnSync {
+  fn new(
+    table: &str,
+    primary: &str,
+    shadow: &str,
+    shadow_type: &str,
+    to_shadow: &str,
+    to_primary: &str,
+  ) -> ColumnSync {
+    ColumnSync {
+      table: table.into(),
+      primary: primary.into(),
+      shadow: shadow.into(),
+      shadow_type: shadow_type.into(),
+      to_shadow: to_shadow.into(),
+      to_primary: to_primary.into(),
+      key: DEFAULT_KEY.into(),
+      key_type: DEFAULT_KEY_TYPE.into(),
+      batch_size: super::DEFAULT_BATCH_SIZE,
+    }
+  }
+
+  fn source(&self, phase: &str) -> String {
+    format!("{phase} {}.{} -> {}", self.table, self.primary, self.shadow)
+  }
+
+  fn trigger_name(&self) -> (String, String) {
+    let (schema, table) = match self.table.rsplit_once('.') {
+      Some((schema, table)) => (Some(schema), table),
+      None => (None, self.table.as_str()),
+    };
+    let name = quote_ident(&sync_name(table, &self.primary));
+    let function = match schema {
+      Some(schema) => format!("{}.{name}", quote_table(schema)),
+      None => name.clone(),
+    };
+
+    (name, function)
+  }
+
+  fn expand(&self, version: Version) -> GeneratedMigration {
+    let table = quote_table(&self.table);
+    let shadow = quote_ident(&self.shadow);
+
+    let up = format!(
+      "ALTER TABLE {table} ADD COLUMN {shadow} {};\n{}",
+      self.shadow_type,
+      self.create_trigger()
+    );
+    let down = format!(
+      "{}\nALTER TABLE {table} DROP COLUMN {shadow};\n",
+      self.drop_trigger()
+    );
+
+    GeneratedMigration::new(version, up, Some(down), self.source("expand"))
+  }
+
+  fn backfill(&self, version: Version) -> DataMigration<ColumnBackfill> {
+    let table = quote_table(&self.table);
+    let key = quote_ident(&self.key);
+    let shadow = quote_ident(&self.shadow);
+    let value = convert(
+      &self.to_shadow,
+      &format!("t.{}", quote_ident(&self.primary)),
+    );
+
+    let batch = format!(
+      "WITH batch AS (\n  \
+         SELECT {key} FROM {table}\n  \
+         WHERE $1::text IS NULL OR {key} > $1::text::{key_type}\n  \
+         ORDER BY {key}\n  \
+         LIMIT $2\n\
+       ), updated AS (\n  \
+         UPDATE {table} AS t SET {shadow} = {value}\n  \
+         FROM batch WHERE t.{key} = batch.{key}\n  \
+         RETURNING t.{key}\n\
+       )\n\
+       SELECT count(*), max({key})::text FROM updated",
+      key_type = self.key_type,
+    );
+
+    DataMigration(ColumnBackfill {
+      version,
+      table: self.table.clone(),
+      batch,
+      batch_size: self.batch_size,
+    })
+  }
+
+  fn contract(&self, version: Version, column: &str) -> GeneratedMigration {
+    let up = format!(
+      "{ALLOW_DIRECTIVE}drop-column\n{}\nALTER TABLE {} DROP COLUMN {};\n",
+      self.drop_trigger(),
+      quote_table(&self.table),
+      quote_ident(column),
+    );
+
+    GeneratedMigration::new(version, up, None, self.source("contract"))
+  }
+
+  /// On insert, whichever column was given fills the other. On update, a change to `primary`
+  /// is copied to `shadow`, and a change to `shadow` is copied back unless it merely matches
+  /// `primary`, as when backfilling.
+  fn create_trigger(&self) -> String {
+    let (name, function) = self.trigger_name();
+    let primary = quote_ident(&self.primary);
+    let shadow = quote_ident(&self.shadow);
+    let to_shadow = convert(&self.to_shadow, &format!("NEW.{primary}"));
+    let to_primary = convert(&self.to_primary, &format!("NEW.{shadow}"));
+
+    format!(
+      "CREATE FUNCTION {function}() RETURNS trigger LANGUAGE plpgsql AS $sync$\n\
+       BEGIN\n  \
+         IF TG_OP = 'INSERT' THEN\n    \
+           IF NEW.{shadow} IS NULL THEN\n      \
+             NEW.{shadow} := {to_shadow};\n    \
+           ELSIF NEW.{primary} IS NULL THEN\n      \
+             NEW.{primary} := {to_primary};\n    \
+           END IF;\n  \
+         ELSIF NEW.{primary} IS DISTINCT FROM OLD.{primary} THEN\n    \
+           NEW.{shadow} := {to_shadow};\n  \
+         ELSIF NEW.{shadow} IS DISTINCT FROM OLD.{shadow}\n    \
+           AND NEW.{shadow} IS DISTINCT FROM {to_shadow} THEN\n    \
+           NEW.{primary} := {to_primary};\n  \
+         END IF;\n\n  \
+         RETURN NEW;\n\
+       END\n\
+       $sync$;\n\
+       CREATE TRIGGER {name} BEFORE INSERT OR UPDATE ON {table}\n  \
+         FOR EACH ROW EXECUTE FUNCTION {function}();\n",
+      table = quote_table(&self.table),
+    )
+  }
+
+  fn drop_trigger(&self) -> String {
+    let (name, function) = self.trigger_name();
+
+    format!(
+      "DROP TRIGGER IF EXISTS {name} ON {};\nDROP FUNCTION IF EXISTS {function}();",
+      quote_table(&self.table)
+    )
+  }
+}
+
+pub struct ColumnBackfill {
+  version: Version,
+  table: String,
+  batch: String,
+  batch_size: u64,
+}
+
+#[async_trait]
+impl BatchedMigration for ColumnBackfill {
+  fn version(&self) -> Version {
+    self.version.clone()
+  }
+
+  fn batch_size(&self) -> u64 {
+    self.batch_size
+  }
+
+  async fn total(&self, conn: &tokio_postgres::Client) -> Result<Option<u64>, MigrationError> {
+    let row = conn
+      .query_one(ESTIMATE_ROWS, &[&quote_table(&self.table)])
+      .await?;
+
+    Ok(row.get::<_, Option<i64>>(0).map(|rows| rows.max(0) as u64))
+  }
+
+  async fn run_batch(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+    cursor: Option<&str>,
+    batch_size: u64,
+  ) -> Result<Batch, MigrationError> {
+    let row = conn
+      .query_one(&self.batch, &[&cursor, &(batch_size as i64)])
+      .await?;
+    let rows = row.get::<_, i64>(0) as u64;
+
+    Ok(Batch {
+      rows,
+      cursor: match rows < batch_size {
+        true => None,
+        false => row.get(1),
+      },
+    })
+  }
+}
+
+pub struct GeneratedMigration {
+  version: Version,
+  up: String,
+  down: Option<String>,
+  checksum: String,
+  source: String,
+}
+
+impl GeneratedMigration {
+  pub fn new(version: Version, up: String, down: Option<String>, source: String) -> Self {
+    GeneratedMigration {
+      version,
+      checksum: checksum(&up),
+      up,
+      down,
+      source,
+    }
+  }
+}
+
+#[async_trait]
+impl Migration for GeneratedMigration {
+  fn version(&self) -> Version {
+    self.version.clone()
+  }
+
+  fn checksum(&self) -> Option<String> {
+    Some(self.checksum.clone())
+  }
+
+  fn source(&self) -> Option<&str> {
+    Some(&self.source)
+  }
+
+  fn sql(&self) -> Option<&str> {
+    Some(&self.up)
+  }
+
+  async fn do_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    conn.batch_execute(&self.up).await?;
+
+    Ok(())
+  }
+
+  fn is_reversible(&self) -> bool {
+    self.down.is_some()
+  }
+
+  async fn revert_migration(
+    &self,
+    conn: &mut tokio_postgres::Transaction<'_>,
+  ) -> Result<(), MigrationError> {
+    match &self.down {
+      Some(down) => conn.batch_execute(down).await?,
+      None => return Err(MigrationError::Irreversible(self.version.clone())),
+    }
+
+    Ok(())
+  }
+}
+
+/// Postgres truncates identifiers to 63 bytes, so long names are cut and made unique again with a
+/// hash of the full name, as two triggers sharing a name would replace each other's function.
+fn sync_name(table: &str, column: &str) -> String {
+  let name = format!("{table}_{column}_sync");
+
+  if name.len() <= MAX_IDENTIFIER_LENGTH {
+    return name;
+  }
+
+  let hash = &checksum(&name)[..8];
+  let mut end = MAX_IDENTIFIER_LENGTH - hash.len() - "__sync".len();
+
+  while !name.is_char_boundary(end) {
+    end -= 1;
+  }
+
+  format!("{}_{hash}_sync", &name[..end])
+}
+
+fn quote_table(table: &str) -> String {
+  table
+    .split('.')
+    .map(quote_ident)
+    .collect::<Vec<_>>()
+    .join(".")
+}
+
+fn convert(template: &str, value: &str) -> String {
+  template.replace("{}", value)
+}
+
+const ESTIMATE_ROWS: &str = r#"
+
+SELECT
+  reltuples::bigint
+FROM
+  pg_class
+WHERE
+  oid = $1::text::regclass
+
+"#;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn trigger_in_table_schema() {
+    let rename = RenameColumn::new("app.account", "mail", "email", "text");
+    let sql = rename.expand(Version::new(1, 0, 0)).up;
+
+    assert!(sql.starts_with("ALTER TABLE \"app\".\"account\" ADD COLUMN \"email\" text;"));
+    assert!(sql.contains("CREATE FUNCTION \"app\".\"account_mail_sync\"()"));
+    assert!(sql.contains("CREATE TRIGGER \"account_mail_sync\" BEFORE INSERT OR UPDATE"));
+  }
+
+  #[test]
+  fn sync_names() {
+    let table = "a".repeat(40);
+    let long = sync_name(&table, &format!("{}_1", "b".repeat(30)));
+    let other = sync_name(&table, &format!("{}_2", "b".repeat(30)));
+
+    assert_eq!(sync_name("account", "mail"), "account_mail_sync");
+    assert_eq!(
+      sync_name(&"a".repeat(53), "b"),
+      format!("{}_b_sync", "a".repeat(53))
+    );
+    assert_eq!(long.len(), 63);
+    assert!(
+      long.starts_with(&format!("{table}_bbbb")) && long.ends_with("_sync"),
+      "{long}"
+    );
+    assert_ne!(long, other);
+
+    // Cut on a character boundary.
+    let name = sync_name(&"é".repeat(40), "mail");
+    assert!(name.len() <= 63 && name.ends_with("_sync"), "{name}");
+
+    let rename = RenameColumn::new(&format!("app.{table}"), &"b".repeat(32), "c", "text");
+    let sql = rename.expand(Version::new(1, 0, 0)).up;
+    let (name, _) = rename.sync.trigger_name();
+    assert!(sql.contains(&format!("CREATE TRIGGER {name} BEFORE")));
+    assert!(sql.contains(&format!("FUNCTION \"app\".{name}()")));
+  }
+
+  #[test]
+  fn sync_trigger() {
+    let change = ChangeColumnType::new("event", "at", "bigint", "timestamptz")
+      .with_conversion("to_timestamp({})", "extract(epoch FROM {})::bigint");
+
+    assert_eq!(
+      change.sync.create_trigger(),
+      r#"CREATE FUNCTION "event_at_sync"() RETURNS trigger LANGUAGE plpgsql AS $sync$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    IF NEW."at_new" IS NULL THEN
+      NEW."at_new" := to_timestamp(NEW."at");
+    ELSIF NEW."at" IS NULL THEN
+      NEW."at" := extract(epoch FROM NEW."at_new")::bigint;
+    END IF;
+  ELSIF NEW."at" IS DISTINCT FROM OLD."at" THEN
+    NEW."at_new" := to_timestamp(NEW."at");
+  ELSIF NEW."at_new" IS DISTINCT FROM OLD."at_new"
+    AND NEW."at_new" IS DISTINCT FROM to_timestamp(NEW."at") THEN
+    NEW."at" := extract(epoch FROM NEW."at_new")::bigint;
+  END IF;
+
+  RETURN NEW;
+END
+$sync$;
+CREATE TRIGGER "event_at_sync" BEFORE INSERT OR UPDATE ON "event"
+  FOR EACH ROW EXECUTE FUNCTION "event_at_sync"();
+"#
+    );
+    assert_eq!(
+      change.sync.drop_trigger(),
+      "DROP TRIGGER IF EXISTS \"event_at_sync\" ON \"event\";\n\
+       DROP FUNCTION IF EXISTS \"event_at_sync\"();"
+    );
+  }
+
+  #[test]
+  fn backfill_batch() {
+    let rename = RenameColumn::new("account", "mail", "email", "text")
+      .with_key("uuid", "uuid")
+      .with_batch_size(500);
+    let DataMigration(backfill) = rename.backfill(Version::new(1, 0, 1));
+
+    assert_eq!(backfill.batch_size, 500);
+    assert_eq!(
+      backfill.batch,
+      r#"WITH batch AS (
+  SELECT "uuid" FROM "account"
+  WHERE $1::text IS NULL OR "uuid" > $1::text::uuid
+  ORDER BY "uuid"
+  LIMIT $2
+), updated AS (
+  UPDATE "account" AS t SET "email" = t."mail"
+  FROM batch WHERE t."uuid" = batch."uuid"
+  RETURNING t."uuid"
+)
+SELECT count(*), max("uuid")::text FROM updated"#
+    );
+  }
+
+  #[test]
+  fn conversion_templates() {
+    let cases = [
+      (
+        ChangeColumnType::new("event", "at", "bigint", "timestamptz"),
+        "t.\"at\"::timestamptz",
+        "NEW.\"at\" := NEW.\"at_new\"::bigint;",
+      ),
+      (
+        ChangeColumnType::new("event", "at", "bigint", "timestamptz")
+          .with_conversion("to_timestamp({})", "extract(epoch FROM {})::bigint"),
+        "to_timestamp(t.\"at\")",
+        "NEW.\"at\" := extract(epoch FROM NEW.\"at_new\")::bigint;",
+      ),
+    ];
+
+    for (change, backfill, to_old) in cases {
+      let DataMigration(batch) = change.backfill(Version::new(1, 0, 1));
+      let expand = change.expand(Version::new(1, 0, 0)).up;
+
+      assert!(
+        batch
+          .batch
+          .contains(&format!("SET \"at_new\" = {backfill}\n")),
+        "{}",
+        batch.batch
+      );
+      assert!(expand.starts_with("ALTER TABLE \"event\" ADD COLUMN \"at_new\" timestamptz;\n"));
+      assert!(expand.contains(to_old), "{expand}");
+    }
+  }
+
+  #[test]
+  fn swap_and_contract() {
+    let change = ChangeColumnType::new("app.event", "at", "bigint", "timestamptz");
+    let swap = change.swap(Version::new(1, 1, 0));
+    let contract = change.contract(Version::new(1, 2, 0));
+    let down = swap.down.as_deref().unwrap();
+
+    // The trigger keeps its name, now syncing the converted column back to `at_old`.
+    assert!(swap.up.starts_with(
+      "DROP TRIGGER IF EXISTS \"event_at_sync\" ON \"app\".\"event\";\n\
+       DROP FUNCTION IF EXISTS \"app\".\"event_at_sync\"();\n\
+       ALTER TABLE \"app\".\"event\" RENAME COLUMN \"at\" TO \"at_old\";\n\
+       ALTER TABLE \"app\".\"event\" RENAME COLUMN \"at_new\" TO \"at\";\n\
+       CREATE FUNCTION \"app\".\"event_at_sync\"()"
+    ));
+    assert!(swap.up.contains("NEW.\"at_old\" := NEW.\"at\"::bigint;"));
+    assert!(swap
+      .up
+      .contains("NEW.\"at\" := NEW.\"at_old\"::timestamptz;"));
+    assert!(down.contains(
+      "ALTER TABLE \"app\".\"event\" RENAME COLUMN \"at\" TO \"at_new\";\n\
+       ALTER TABLE \"app\".\"event\" RENAME COLUMN \"at_old\" TO \"at\";\n"
+    ));
+    assert!(down.contains("NEW.\"at_new\" := NEW.\"at\"::timestamptz;"));
+
+    assert_eq!(
+      contract.up,
+      "-- migrator:allow drop-column\n\
+       DROP TRIGGER IF EXISTS \"event_at_sync\" ON \"app\".\"event\";\n\
+       DROP FUNCTION IF EXISTS \"app\".\"event_at_sync\"();\n\
+       ALTER TABLE \"app\".\"event\" DROP COLUMN \"at_old\";\n"
+    );
+    assert!(contract.down.is_none());
+
+    let rename = RenameColumn::new("account", "mail", "email", "text");
+    assert!(rename
+      .contract(Version::new(1, 1, 0))
+      .up
+      .ends_with("ALTER TABLE \"account\" DROP COLUMN \"mail\";\n"));
+  }
+}
@@ -0,0 +1,344 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use futures::{stream, StreamExt};
+
+use crate::db::PgPool;
+use crate::version::Version;
+
+use super::{Migration, MigrationInitError, Migrator, MigratorTables, DEFAULT_LOCK_TIMEOUT};
+
+enum Schemas {
+  List(Vec<String>),
+  Matching(String),
+}
+
+/// Every schema is migrated on its own connection, so parallelism is bounded by the pool size.
+pub struct SchemaMigrator<'a> {
+  module_name: String,
+  pool: PgPool,
+  migrations: Vec<Box<dyn Migration + 'a>>,
+  schemas: Schemas,
+  parallelism: usize,
+  continue_on_error: bool,
+  lock_timeout: Option<Duration>,
+  tables: MigratorTables,
+  configure: Option<Box<ConfigureMigrator<'a>>>,
+}
+
+type ConfigureMigrator<'a> = dyn for<'m> Fn(Migrator<'m>) -> Migrator<'m> + Send + Sync + 'a;
+
+impl<'a> SchemaMigrator<'a> {
+  pub fn new(
+    module_name: &str,
+    pool: PgPool,
+    migrations: Vec<Box<dyn Migration + 'a>>,
+  ) -> SchemaMigrator<'a> {
+    SchemaMigrator {
+      module_name: module_name.into(),
+      pool,
+      migrations,
+      schemas: Schemas::List(vec![]),
+      parallelism: 1,
+      continue_on_error: false,
+      lock_timeout: Some(DEFAULT_LOCK_TIMEOUT),
+      tables: MigratorTables::default(),
+      configure: None,
+    }
+  }
+
+  pub fn with_schemas(mut self, schemas: Vec<String>) -> Self {
+    self.schemas = Schemas::List(schemas);
+    self
+  }
+
+  pub fn with_schema_pattern(mut self, pattern: &str) -> Self {
+    self.schemas = Schemas::Matching(pattern.into());
+    self
+  }
+
+  pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+    self.parallelism = parallelism.max(1);
+    self
+  }
+
+  pub fn with_continue_on_error(mut self, continue_on_error: bool) -> Self {
+    self.continue_on_error = continue_on_error;
+    self
+  }
+
+  pub fn with_lock_timeout(mut self, lock_timeout: Option<Duration>) -> Self {
+    self.lock_timeout = lock_timeout;
+    self
+  }
+
+  pub fn with_tables(mut self, tables: MigratorTables) -> Self {
+    self.tables = tables;
+    self
+  }
+
+  /// Applied to every schema's `Migrator`, for a baseline, repeatable migrations, seeds, lint
+  /// policy, timeouts or lock retries. The schema is set afterwards and cannot be overridden.
+  pub fn with_migrator<F>(mut self, configure: F) -> Self
+  where
+    F: for<'m> Fn(Migrator<'m>) -> Migrator<'m> + Send + Sync + 'a,
+  {
+    self.configure = Some(Box::new(configure));
+    self
+  }
+
+  pub async fn schemas(&self) -> Result<Vec<String>, MigrationInitError> {
+    match &self.schemas {
+      Schemas::List(schemas) => Ok(schemas.clone()),
+      Schemas::Matching(pattern) => {
+        let rows = self
+          .pool
+          .get()
+          .await?
+          .query(GET_MATCHING_SCHEMAS, &[pattern])
+          .await
+          .map_err(super::MigrationError::from)?;
+
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+      }
+    }
+  }
+
+  pub async fn migrate(&self) -> Result<SchemaMigrationSummary, MigrationInitError> {
+    let schemas = self.schemas().await?;
+    let failed = AtomicBool::new(false);
+
+    let results: Vec<SchemaResult> = stream::iter(schemas)
+      .map(|schema| {
+        let failed = &failed;
+
+        async move {
+          if failed.load(Ordering::Relaxed) && !self.continue_on_error {
+            return SchemaResult {
+              schema,
+              outcome: SchemaOutcome::Skipped,
+            };
+          }
+
+          let outcome = match self.migrate_schema(&schema).await {
+            Ok(version) => SchemaOutcome::Migrated(version),
+            Err(err) => {
+              failed.store(true, Ordering::Relaxed);
+
+              SchemaOutcome::Failed(err)
+            }
+          };
+
+          SchemaResult { schema, outcome }
+        }
+      })
+      .buffered(self.parallelism)
+      .collect()
+      .await;
+
+    let summary = SchemaMigrationSummary {
+      module_name: self.module_name.clone(),
+      results,
+    };
+
+    summary.log();
+
+    Ok(summary)
+  }
+
+  async fn migrate_schema(&self, schema: &str) -> Result<Version, MigrationInitError> {
+    let migrations = self
+      .migrations
+      .iter()
+      .map(|migration| Box::new(&**migration) as Box<dyn Migration + '_>)
+      .collect();
+
+    let migrator = Migrator::new(&self.module_name, self.pool.get().await?, migrations)
+      .with_lock_timeout(self.lock_timeout)
+      .with_tables(self.tables.clone());
+    let mut migrator = match &self.configure {
+      Some(configure) => configure(migrator),
+      None => migrator,
+    }
+    .with_schema(schema);
+
+    migrator.migrate().await?;
+
+    Ok(migrator.get_version().await?)
+  }
+}
+
+#[derive(Debug)]
+pub enum SchemaOutcome {
+  Migrated(Version),
+  Failed(MigrationInitError),
+  Skipped,
+}
+
+#[derive(Debug)]
+pub struct SchemaResult {
+  pub schema: String,
+  pub outcome: SchemaOutcome,
+}
+
+#[derive(Debug)]
+pub struct SchemaMigrationSummary {
+  pub module_name: String,
+  pub results: Vec<SchemaResult>,
+}
+
+impl SchemaMigrationSummary {
+  pub fn is_success(&self) -> bool {
+    self
+      .results
+      .iter()
+      .all(|result| matches!(result.outcome, SchemaOutcome::Migrated(_)))
+  }
+
+  pub fn failed(&self) -> impl Iterator<Item = &SchemaResult> {
+    self
+      .results
+      .iter()
+      .filter(|result| matches!(result.outcome, SchemaOutcome::Failed(_)))
+  }
+
+  fn log(&self) {
+    let count = |f: fn(&SchemaOutcome) -> bool| {
+      self
+        .results
+        .iter()
+        .filter(|result| f(&result.outcome))
+        .count()
+    };
+
+    for result in self.failed() {
+      if let SchemaOutcome::Failed(err) = &result.outcome {
+        error!(
+          "[{}] Failed migrating schema {}: {}",
+          self.module_name, result.schema, err
+        );
+      }
+    }
+
+    info!(
+      "[{}] Migrated {} schemas, {} failed, {} skipped",
+      self.module_name,
+      count(|outcome| matches!(outcome, SchemaOutcome::Migrated(_))),
+      count(|outcome| matches!(outcome, SchemaOutcome::Failed(_))),
+      count(|outcome| matches!(outcome, SchemaOutcome::Skipped)),
+    );
+  }
+}
+
+const GET_MATCHING_SCHEMAS: &str = r#"
+
+SELECT
+  nspname
+FROM
+  pg_namespace
+WHERE
+  nspname LIKE $1
+ORDER BY
+  nspname
+
+"#;
+
+#[cfg(test)]
+mod tests {
+  use crate::config::DatabaseConfig;
+  use crate::migration::{MigrationInitError, PlainMigration};
+  use crate::version::Version;
+
+  use super::{SchemaMigrationSummary, SchemaMigrator, SchemaOutcome, SchemaResult};
+
+  fn result(schema: &str, outcome: SchemaOutcome) -> SchemaResult {
+    SchemaResult {
+      schema: schema.into(),
+      outcome,
+    }
+  }
+
+  fn failure() -> SchemaOutcome {
+    SchemaOutcome::Failed(MigrationInitError::DatabaseConfigError(
+      crate::config::DatabaseConfigError::Tls(String::from("failed")),
+    ))
+  }
+
+  #[test]
+  fn summary_outcomes() {
+    let summary = |results| SchemaMigrationSummary {
+      module_name: String::from("m"),
+      results,
+    };
+
+    let migrated = summary(vec![
+      result("a", SchemaOutcome::Migrated(Version::new(1, 0, 0))),
+      result("b", SchemaOutcome::Migrated(Version::new(1, 0, 0))),
+    ]);
+    assert!(migrated.is_success());
+    assert_eq!(migrated.failed().count(), 0);
+
+    let failed = summary(vec![
+      result("a", SchemaOutcome::Migrated(Version::new(1, 0, 0))),
+      result("b", failure()),
+      result("c", SchemaOutcome::Skipped),
+    ]);
+    assert!(!failed.is_success());
+    assert_eq!(
+      failed
+        .failed()
+        .map(|result| &*result.schema)
+        .collect::<Vec<_>>(),
+      ["b"]
+    );
+
+    // Skipped schemas were not migrated either.
+    assert!(!summary(vec![result("a", SchemaOutcome::Skipped)]).is_success());
+    assert!(summary(vec![]).is_success());
+  }
+
+  #[tokio::test]
+  async fn skip_after_failure() {
+    // Nothing listens on port 1, so every schema fails to get a connection.
+    let config = DatabaseConfig {
+      port: 1,
+      ..DatabaseConfig::default()
+    };
+    let pool = config.get_db_pool().await.unwrap();
+    let schemas = vec![String::from("a"), String::from("b"), String::from("c")];
+    let migrator = |continue_on_error| {
+      SchemaMigrator::new(
+        "m",
+        pool.clone(),
+        vec![Box::new(PlainMigration::new(
+          Version::new(1, 0, 0),
+          "SELECT 1;",
+        ))],
+      )
+      .with_schemas(schemas.clone())
+      .with_continue_on_error(continue_on_error)
+      .with_migrator(|migrator| migrator.with_lock_timeout(None))
+    };
+
+    let outcomes = |summary: SchemaMigrationSummary| {
+      summary
+        .results
+        .into_iter()
+        .map(|result| match result.outcome {
+          SchemaOutcome::Migrated(_) => "migrated",
+          SchemaOutcome::Failed(_) => "failed",
+          SchemaOutcome::Skipped => "skipped",
+        })
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+      outcomes(migrator(false).migrate().await.unwrap()),
+      ["failed", "skipped", "skipped"]
+    );
+    assert_eq!(
+      outcomes(migrator(true).migrate().await.unwrap()),
+      ["failed", "failed", "failed"]
+    );
+  }
+}
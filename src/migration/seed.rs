@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+
+use futures::future::BoxFuture;
+
+use super::{checksum, MigrationError};
+
+pub const ENVIRONMENT_VAR: &str = "FUZION_ENVIRONMENT";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+  Dev,
+  Test,
+  Staging,
+  Production,
+}
+
+impl Environment {
+  pub const ALL: [Environment; 4] = [
+    Environment::Dev,
+    Environment::Test,
+    Environment::Staging,
+    Environment::Production,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Environment::Dev => "dev",
+      Environment::Test => "test",
+      Environment::Staging => "staging",
+      Environment::Production => "production",
+    }
+  }
+
+  /// `None` when `FUZION_ENVIRONMENT` is unset or empty, an error when it names no environment.
+  pub fn from_env() -> Result<Option<Environment>, String> {
+    match std::env::var(ENVIRONMENT_VAR) {
+      Ok(value) if !value.is_empty() => value
+        .parse()
+        .map(Some)
+        .map_err(|err| format!("{ENVIRONMENT_VAR}: {err}")),
+      Ok(_) | Err(std::env::VarError::NotPresent) => Ok(None),
+      Err(err) => Err(format!("{ENVIRONMENT_VAR}: {err}")),
+    }
+  }
+}
+
+impl FromStr for Environment {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    Environment::ALL
+      .into_iter()
+      .find(|environment| environment.name().eq_ignore_ascii_case(value))
+      .ok_or_else(|| format!("unknown environment {value}"))
+  }
+}
+
+impl fmt::Display for Environment {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+pub type SeedFn = dyn for<'c> Fn(&'c tokio_postgres::Transaction<'c>) -> BoxFuture<'c, Result<(), MigrationError>>
+  + Send
+  + Sync;
+
+pub(crate) enum SeedKind {
+  Script(&'static str),
+  Closure(Box<SeedFn>),
+}
+
+/// A single seed of a `SeedSet`. Seeds run again whenever their checksum changes, so they must
+/// be idempotent, e.g. `INSERT ... ON CONFLICT DO NOTHING`.
+pub struct Seed {
+  pub(crate) name: &'static str,
+  pub(crate) checksum: String,
+  pub(crate) kind: SeedKind,
+}
+
+impl Seed {
+  pub fn name(&self) -> &str {
+    self.name
+  }
+}
+
+pub struct SeedSet {
+  pub(crate) name: &'static str,
+  pub(crate) environments: Vec<Environment>,
+  pub(crate) seeds: Vec<Seed>,
+}
+
+impl SeedSet {
+  pub fn new(name: &'static str, environments: &[Environment]) -> SeedSet {
+    SeedSet {
+      name,
+      environments: environments.to_vec(),
+      seeds: vec![],
+    }
+  }
+
+  pub fn with_script(mut self, name: &'static str, query: &'static str) -> Self {
+    self.seeds.push(Seed {
+      name,
+      checksum: checksum(query),
+      kind: SeedKind::Script(query),
+    });
+    self
+  }
+
+  /// Adds a closure. Its code cannot be fingerprinted, so it reruns whenever `revision` changes.
+  pub fn with_closure<F>(mut self, name: &'static str, revision: &str, seed: F) -> Self
+  where
+    F: for<'c> Fn(&'c tokio_postgres::Transaction<'c>) -> BoxFuture<'c, Result<(), MigrationError>>
+      + Send
+      + Sync
+      + 'static,
+  {
+    self.seeds.push(Seed {
+      name,
+      checksum: checksum(revision),
+      kind: SeedKind::Closure(Box::new(seed)),
+    });
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    self.name
+  }
+
+  pub fn seeds(&self) -> &[Seed] {
+    &self.seeds
+  }
+
+  pub fn applies_to(&self, environment: Environment) -> bool {
+    self.environments.contains(&environment)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures::FutureExt;
+
+  use super::{Environment, SeedSet, ENVIRONMENT_VAR};
+  use crate::migration::checksum;
+
+  #[test]
+  fn parse_environment() {
+    let cases = [
+      ("dev", Ok(Environment::Dev)),
+      ("TEST", Ok(Environment::Test)),
+      ("Staging", Ok(Environment::Staging)),
+      ("production", Ok(Environment::Production)),
+      ("development", Err(())),
+      ("prod", Err(())),
+      ("", Err(())),
+    ];
+
+    for (value, environment) in cases {
+      assert_eq!(
+        value.parse::<Environment>().map_err(|_| ()),
+        environment,
+        "{value:?}"
+      );
+    }
+
+    for environment in Environment::ALL {
+      assert_eq!(environment.to_string().parse(), Ok(environment));
+    }
+  }
+
+  #[test]
+  fn environment_from_env() {
+    let cases = [
+      (None, Ok(None)),
+      (Some(""), Ok(None)),
+      (Some("staging"), Ok(Some(Environment::Staging))),
+      (
+        Some("prod"),
+        Err(String::from("FUZION_ENVIRONMENT: unknown environment prod")),
+      ),
+    ];
+
+    for (value, environment) in cases {
+      match value {
+        Some(value) => std::env::set_var(ENVIRONMENT_VAR, value),
+        None => std::env::remove_var(ENVIRONMENT_VAR),
+      }
+
+      assert_eq!(Environment::from_env(), environment, "{value:?}");
+    }
+
+    std::env::remove_var(ENVIRONMENT_VAR);
+  }
+
+  #[test]
+  fn seed_sets() {
+    let set = SeedSet::new("fixtures", &[Environment::Dev, Environment::Test])
+      .with_script("users", "INSERT INTO users VALUES (1);")
+      .with_closure("accounts", "2", |_| async { Ok(()) }.boxed());
+
+    assert!(set.applies_to(Environment::Dev));
+    assert!(set.applies_to(Environment::Test));
+    assert!(!set.applies_to(Environment::Production));
+    assert!(!SeedSet::new("none", &[]).applies_to(Environment::Dev));
+
+    let seeds: Vec<_> = set
+      .seeds()
+      .iter()
+      .map(|seed| (seed.name(), seed.checksum.as_str()))
+      .collect();
+
+    assert_eq!(
+      seeds,
+      [
+        ("users", checksum("INSERT INTO users VALUES (1);").as_str()),
+        ("accounts", checksum("2").as_str()),
+      ]
+    );
+
+    // Only the revision identifies a closure's content.
+    let revised =
+      SeedSet::new("fixtures", &[]).with_closure("accounts", "3", |_| async { Ok(()) }.boxed());
+    assert_ne!(revised.seeds()[0].checksum, set.seeds()[1].checksum);
+  }
+}
@@ -0,0 +1,285 @@
+pub(crate) fn split_statements(sql: &str) -> Vec<&str> {
+  let bytes = sql.as_bytes();
+  let mut statements = vec![];
+  let mut start = 0;
+  let mut i = 0;
+
+  while i < bytes.len() {
+    match bytes[i] {
+      b'\'' | b'"' => i = skip_quoted(bytes, i, bytes[i]),
+      b'-' if bytes.get(i + 1) == Some(&b'-') => {
+        i = match sql[i..].find('\n') {
+          Some(end) => i + end + 1,
+          None => bytes.len(),
+        };
+      }
+      b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_block_comment(bytes, i),
+      b'$' => match dollar_tag(&sql[i..]) {
+        Some(tag) => {
+          i = match sql[i + tag.len()..].find(tag) {
+            Some(end) => i + tag.len() + end + tag.len(),
+            None => bytes.len(),
+          };
+        }
+        None => i += 1,
+      },
+      b';' => {
+        push_statement(&mut statements, &sql[start..i]);
+        i += 1;
+        start = i;
+      }
+      _ => i += 1,
+    }
+  }
+
+  push_statement(&mut statements, &sql[start..]);
+
+  statements
+}
+
+fn push_statement<'a>(statements: &mut Vec<&'a str>, statement: &'a str) {
+  let statement = statement.trim();
+
+  if !strip_comments(statement).trim().is_empty() {
+    statements.push(statement);
+  }
+}
+
+pub(crate) fn strip_comments(sql: &str) -> String {
+  let bytes = sql.as_bytes();
+  let mut output = String::with_capacity(sql.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    let start = i;
+
+    match bytes[i] {
+      b'\'' | b'"' => i = skip_quoted(bytes, i, bytes[i]),
+      b'-' if bytes.get(i + 1) == Some(&b'-') => {
+        i = match sql[i..].find('\n') {
+          Some(end) => i + end,
+          None => bytes.len(),
+        };
+        output.push(' ');
+        continue;
+      }
+      b'/' if bytes.get(i + 1) == Some(&b'*') => {
+        i = skip_block_comment(bytes, i);
+        output.push(' ');
+        continue;
+      }
+      b'$' => match dollar_tag(&sql[i..]) {
+        Some(tag) => {
+          i = match sql[i + tag.len()..].find(tag) {
+            Some(end) => i + tag.len() + end + tag.len(),
+            None => bytes.len(),
+          };
+        }
+        None => i += 1,
+      },
+      _ => {
+        i += sql[i..].chars().next().map(char::len_utf8).unwrap_or(1);
+      }
+    }
+
+    output.push_str(&sql[start..i]);
+  }
+
+  output
+}
+
+pub(crate) fn tokenize(sql: &str) -> Vec<&str> {
+  let bytes = sql.as_bytes();
+  let mut tokens = vec![];
+  let mut i = 0;
+
+  let skip_word = |mut i: usize| {
+    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"()',;\"".contains(&bytes[i]) {
+      i += 1;
+    }
+
+    i
+  };
+
+  while i < bytes.len() {
+    let start = i;
+
+    match bytes[i] {
+      byte if byte.is_ascii_whitespace() => {
+        i += 1;
+        continue;
+      }
+      b'-' if bytes.get(i + 1) == Some(&b'-') => {
+        i = match sql[i..].find('\n') {
+          Some(end) => i + end + 1,
+          None => bytes.len(),
+        };
+        continue;
+      }
+      b'/' if bytes.get(i + 1) == Some(&b'*') => {
+        i = skip_block_comment(bytes, i);
+        continue;
+      }
+      b'\'' | b'"' => i = skip_quoted(bytes, i, bytes[i]),
+      b'(' | b')' | b',' | b';' => i += 1,
+      b'$' => match dollar_tag(&sql[i..]) {
+        Some(tag) => {
+          i = match sql[i + tag.len()..].find(tag) {
+            Some(end) => i + tag.len() + end + tag.len(),
+            None => bytes.len(),
+          };
+        }
+        None => i = skip_word(i + 1),
+      },
+      _ => i = skip_word(i + 1),
+    }
+
+    tokens.push(&sql[start..i]);
+  }
+
+  tokens
+}
+
+pub(crate) fn identifier(token: &str) -> String {
+  let name = token.rsplit('.').next().unwrap_or(token);
+
+  match name
+    .strip_prefix('"')
+    .and_then(|name| name.strip_suffix('"'))
+  {
+    Some(quoted) => quoted.replace("\"\"", "\""),
+    None => name.to_lowercase(),
+  }
+}
+
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+  let mut i = start + 1;
+
+  while i < bytes.len() {
+    if bytes[i] == quote {
+      // A doubled quote is an escaped quote.
+      if bytes.get(i + 1) == Some(&quote) {
+        i += 2;
+        continue;
+      }
+
+      return i + 1;
+    }
+
+    i += 1;
+  }
+
+  bytes.len()
+}
+
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+  let mut depth = 0;
+  let mut i = start;
+
+  while i < bytes.len() {
+    if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+      depth += 1;
+      i += 2;
+    } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+      depth -= 1;
+      i += 2;
+
+      if depth == 0 {
+        return i;
+      }
+    } else {
+      i += 1;
+    }
+  }
+
+  bytes.len()
+}
+
+fn dollar_tag(sql: &str) -> Option<&str> {
+  let end = sql[1..].find('$')? + 2;
+  let tag = &sql[..end];
+
+  match tag[1..end - 1]
+    .chars()
+    .all(|c| c.is_alphanumeric() || c == '_')
+    && !tag[1..].starts_with(|c: char| c.is_ascii_digit())
+  {
+    true => Some(tag),
+    false => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{split_statements, strip_comments, tokenize};
+
+  #[test]
+  fn split_simple() {
+    assert_eq!(
+      split_statements("CREATE TABLE a (x int);\nDROP TABLE b;\n"),
+      vec!["CREATE TABLE a (x int)", "DROP TABLE b"]
+    );
+  }
+
+  #[test]
+  fn split_ignores_quoted_semicolons() {
+    assert_eq!(
+      split_statements("INSERT INTO a VALUES ('x;''y'); SELECT \"a;b\" FROM c"),
+      vec!["INSERT INTO a VALUES ('x;''y')", "SELECT \"a;b\" FROM c"]
+    );
+  }
+
+  #[test]
+  fn split_ignores_dollar_quoted_bodies() {
+    let sql = "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN; RETURN 1; END $body$ LANGUAGE plpgsql;\nSELECT $1;";
+
+    assert_eq!(
+      split_statements(sql),
+      vec![
+        "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN; RETURN 1; END $body$ LANGUAGE plpgsql",
+        "SELECT $1",
+      ]
+    );
+  }
+
+  #[test]
+  fn split_ignores_comments() {
+    assert_eq!(
+      split_statements("-- a; b\nSELECT 1; /* c; */\n-- trailing"),
+      vec!["-- a; b\nSELECT 1"]
+    );
+  }
+
+  #[test]
+  fn tokenize_statement() {
+    assert_eq!(
+      tokenize("ALTER TABLE \"A b\" ADD x int DEFAULT nextval('s'::regclass), -- c\nDROP y;"),
+      vec![
+        "ALTER",
+        "TABLE",
+        "\"A b\"",
+        "ADD",
+        "x",
+        "int",
+        "DEFAULT",
+        "nextval",
+        "(",
+        "'s'",
+        "::regclass",
+        ")",
+        ",",
+        "DROP",
+        "y",
+        ";"
+      ]
+    );
+  }
+
+  #[test]
+  fn strip_comments_keeps_strings() {
+    assert_eq!(
+      strip_comments("SELECT '--x' -- y\n/* z */ FROM a").trim(),
+      "SELECT '--x'  \n  FROM a"
+    );
+  }
+}
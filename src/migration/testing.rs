@@ -0,0 +1,349 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::config::{DatabaseConfig, DatabaseConfigError};
+use crate::db::{DeadpoolPoolError, PgPool};
+use crate::version::Version;
+
+use super::{Migration, MigrationError, Migrator, DEFAULT_MIGRATOR_SCHEMA};
+
+static DATABASE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A database created for a single test. Call `destroy` when done, as the database is left
+/// behind otherwise.
+pub struct TestDatabase {
+  admin: DatabaseConfig,
+  config: DatabaseConfig,
+  pool: PgPool,
+}
+
+impl TestDatabase {
+  pub async fn create(config: &DatabaseConfig) -> Result<TestDatabase, MigrationTestError> {
+    let since_epoch = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    let name = format!(
+      "fuzion_test_{}_{}_{}",
+      std::process::id(),
+      since_epoch.as_millis(),
+      DATABASE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let admin_pool = config.get_db_pool().await?;
+    admin_pool
+      .get()
+      .await?
+      .batch_execute(&format!("CREATE DATABASE \"{name}\""))
+      .await?;
+
+    let mut test_config = config.clone();
+    test_config.name = name;
+
+    let pool = match test_config.get_db_pool().await {
+      Ok(pool) => pool,
+      Err(err) => {
+        if let Ok(client) = admin_pool.get().await {
+          let _ = client
+            .batch_execute(&format!(
+              "DROP DATABASE IF EXISTS \"{}\" WITH (FORCE)",
+              test_config.name
+            ))
+            .await;
+        }
+
+        return Err(err.into());
+      }
+    };
+
+    Ok(TestDatabase {
+      admin: config.clone(),
+      config: test_config,
+      pool,
+    })
+  }
+
+  pub fn config(&self) -> &DatabaseConfig {
+    &self.config
+  }
+
+  pub fn pool(&self) -> &PgPool {
+    &self.pool
+  }
+
+  pub async fn destroy(self) -> Result<(), MigrationTestError> {
+    let TestDatabase {
+      admin,
+      config,
+      pool,
+    } = self;
+
+    drop(pool);
+
+    admin
+      .get_db_pool()
+      .await?
+      .get()
+      .await?
+      .batch_execute(&format!(
+        "DROP DATABASE IF EXISTS \"{}\" WITH (FORCE)",
+        config.name
+      ))
+      .await?;
+
+    Ok(())
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+  pub objects: Vec<String>,
+}
+
+impl Schema {
+  pub fn diff(&self, other: &Schema) -> String {
+    let removed = self
+      .objects
+      .iter()
+      .filter(|e| !other.objects.contains(e))
+      .map(|e| format!("- {e}"));
+    let added = other
+      .objects
+      .iter()
+      .filter(|e| !self.objects.contains(e))
+      .map(|e| format!("+ {e}"));
+
+    removed.chain(added).collect::<Vec<_>>().join("\n")
+  }
+}
+
+impl fmt::Display for Schema {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for object in &self.objects {
+      writeln!(f, "{object}")?;
+    }
+
+    Ok(())
+  }
+}
+
+pub async fn dump_schema(client: &tokio_postgres::Client) -> Result<Schema, tokio_postgres::Error> {
+  let rows = client
+    .query(DUMP_SCHEMA, &[&DEFAULT_MIGRATOR_SCHEMA])
+    .await?;
+
+  Ok(Schema {
+    objects: rows.iter().map(|row| row.get(0)).collect(),
+  })
+}
+
+pub struct MigrationTest<'a> {
+  module_name: String,
+  migrations: Vec<Box<dyn Migration + 'a>>,
+  revert: bool,
+}
+
+impl<'a> MigrationTest<'a> {
+  pub fn new(module_name: &str, migrations: Vec<Box<dyn Migration + 'a>>) -> MigrationTest<'a> {
+    MigrationTest {
+      module_name: module_name.into(),
+      migrations,
+      revert: false,
+    }
+  }
+
+  pub fn with_revert(mut self, revert: bool) -> Self {
+    self.revert = revert;
+    self
+  }
+
+  pub async fn run(self, config: &DatabaseConfig) -> Result<Schema, MigrationTestError> {
+    let database = TestDatabase::create(config).await?;
+
+    let result = self.run_in(&database).await;
+    let destroyed = database.destroy().await;
+
+    let schema = result?;
+    destroyed?;
+
+    Ok(schema)
+  }
+
+  async fn run_in(self, database: &TestDatabase) -> Result<Schema, MigrationTestError> {
+    let client = database.pool().get().await?;
+    let mut migrator = Migrator::new(
+      &self.module_name,
+      database.pool().get().await?,
+      self.migrations,
+    );
+
+    let empty = dump_schema(&client).await?;
+
+    migrator.migrate().await?;
+
+    let migrated = dump_schema(&client).await?;
+
+    if !self.revert {
+      return Ok(migrated);
+    }
+
+    migrator.migrate_to(Version::new(0, 0, 0)).await?;
+
+    let reverted = dump_schema(&client).await?;
+
+    if reverted != empty {
+      return Err(MigrationTestError::NotReverted(empty.diff(&reverted)));
+    }
+
+    migrator.migrate().await?;
+
+    let reapplied = dump_schema(&client).await?;
+
+    if reapplied != migrated {
+      return Err(MigrationTestError::SchemaMismatch(
+        migrated.diff(&reapplied),
+      ));
+    }
+
+    Ok(migrated)
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationTestError {
+  #[error("Reverting all migrations did not restore the empty schema:\n{0}")]
+  NotReverted(String),
+  #[error("Re-applying migrations after reverting them produced a different schema:\n{0}")]
+  SchemaMismatch(String),
+  #[error(transparent)]
+  DatabaseConfigError(#[from] DatabaseConfigError),
+  #[error(transparent)]
+  DeadpoolPoolError(#[from] DeadpoolPoolError),
+  #[error(transparent)]
+  MigrationError(#[from] MigrationError),
+  #[error(transparent)]
+  Postgres(#[from] tokio_postgres::Error),
+}
+
+const DUMP_SCHEMA: &str = r#"
+
+WITH namespace AS (
+  SELECT
+    oid, nspname
+  FROM pg_namespace
+  WHERE
+    nspname NOT IN ('information_schema', $1)
+    AND nspname NOT LIKE 'pg\_%'
+)
+SELECT format('schema %I', n.nspname)
+FROM namespace n
+
+UNION ALL
+
+SELECT format('relation %I.%I %s', n.nspname, c.relname, c.relkind)
+FROM pg_class c
+JOIN namespace n ON n.oid = c.relnamespace
+WHERE
+  c.relkind IN ('r', 'p', 'v', 'm', 'S', 'f', 'c')
+
+UNION ALL
+
+SELECT format(
+  'column %I.%I #%s %I %s%s%s',
+  n.nspname,
+  c.relname,
+  row_number() OVER (PARTITION BY c.oid ORDER BY a.attnum),
+  a.attname,
+  format_type(a.atttypid, a.atttypmod),
+  CASE WHEN a.attnotnull THEN ' NOT NULL' ELSE '' END,
+  coalesce(' DEFAULT ' || pg_get_expr(d.adbin, d.adrelid), '')
+)
+FROM pg_attribute a
+JOIN pg_class c ON c.oid = a.attrelid
+JOIN namespace n ON n.oid = c.relnamespace
+LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+WHERE
+  a.attnum > 0
+  AND NOT a.attisdropped
+  AND c.relkind IN ('r', 'p', 'v', 'm', 'f', 'c')
+
+UNION ALL
+
+SELECT format('constraint %I.%I %I %s', n.nspname, c.relname, con.conname, pg_get_constraintdef(con.oid))
+FROM pg_constraint con
+JOIN pg_class c ON c.oid = con.conrelid
+JOIN namespace n ON n.oid = c.relnamespace
+
+UNION ALL
+
+SELECT format('index %s', pg_get_indexdef(i.indexrelid))
+FROM pg_index i
+JOIN pg_class c ON c.oid = i.indexrelid
+JOIN namespace n ON n.oid = c.relnamespace
+
+UNION ALL
+
+SELECT format('view %I.%I %s', n.nspname, c.relname, pg_get_viewdef(c.oid))
+FROM pg_class c
+JOIN namespace n ON n.oid = c.relnamespace
+WHERE
+  c.relkind IN ('v', 'm')
+
+UNION ALL
+
+SELECT format('sequence %I.%I %s %s %s', s.schemaname, s.sequencename, s.data_type, s.increment_by, s.cycle)
+FROM pg_sequences s
+JOIN namespace n ON n.nspname = s.schemaname
+
+UNION ALL
+
+SELECT format('function %s', pg_get_functiondef(p.oid))
+FROM pg_proc p
+JOIN namespace n ON n.oid = p.pronamespace
+WHERE
+  p.prokind IN ('f', 'p')
+  AND NOT EXISTS (
+    SELECT
+      1
+    FROM pg_depend d
+    WHERE
+      d.classid = 'pg_proc'::regclass
+      AND d.objid = p.oid
+      AND d.deptype = 'e'
+  )
+
+UNION ALL
+
+SELECT format('trigger %s', pg_get_triggerdef(t.oid))
+FROM pg_trigger t
+JOIN pg_class c ON c.oid = t.tgrelid
+JOIN namespace n ON n.oid = c.relnamespace
+WHERE
+  NOT t.tgisinternal
+
+UNION ALL
+
+SELECT format('enum %I.%I %s', n.nspname, t.typname, string_agg(quote_literal(e.enumlabel), ', ' ORDER BY e.enumsortorder))
+FROM pg_type t
+JOIN pg_enum e ON e.enumtypid = t.oid
+JOIN namespace n ON n.oid = t.typnamespace
+GROUP BY n.nspname, t.typname
+
+UNION ALL
+
+SELECT format('domain %I.%I %s', n.nspname, t.typname, format_type(t.typbasetype, t.typtypmod))
+FROM pg_type t
+JOIN namespace n ON n.oid = t.typnamespace
+WHERE
+  t.typtype = 'd'
+
+UNION ALL
+
+SELECT format('extension %I', x.extname)
+FROM pg_extension x
+
+ORDER BY 1
+
+"#;
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use regex::Regex;
 use serde::{de, ser, Deserializer, Serializer};
 
@@ -56,6 +58,51 @@ where
   s.serialize_str(&regex.to_string())
 }
 
+pub use fuzion_commons_directives::parse_duration;
+
+/// Formats a duration in the largest unit `parse_duration` reads it back from exactly. Fractions
+/// of a millisecond are rounded up, as a timeout of 0 disables it.
+pub fn format_duration(duration: Duration) -> String {
+  let millis = ceil_millis(duration);
+
+  match millis {
+    0 => String::from("0s"),
+    _ if millis.is_multiple_of(3_600_000) => format!("{}h", millis / 3_600_000),
+    _ if millis.is_multiple_of(60_000) => format!("{}min", millis / 60_000),
+    _ if millis.is_multiple_of(1_000) => format!("{}s", millis / 1_000),
+    _ => format!("{millis}ms"),
+  }
+}
+
+/// Whole milliseconds, rounded up.
+pub(crate) fn ceil_millis(duration: Duration) -> u128 {
+  duration.as_nanos().div_ceil(1_000_000)
+}
+
+pub fn deserialize_optional_duration<'de, D>(de: D) -> Result<Option<Duration>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let duration: Option<String> = de::Deserialize::deserialize(de)?;
+
+  duration
+    .map(|duration| {
+      parse_duration(&duration)
+        .ok_or_else(|| de::Error::custom(format!("Invalid duration {duration}")))
+    })
+    .transpose()
+}
+
+pub fn serialize_optional_duration<S>(duration: &Option<Duration>, s: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  match duration {
+    Some(duration) => s.serialize_some(&format_duration(*duration)),
+    None => s.serialize_none(),
+  }
+}
+
 pub fn default_true() -> bool {
   true
 }
@@ -63,3 +110,87 @@ pub fn default_true() -> bool {
 pub fn default_false() -> bool {
   false
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{format_duration, parse_duration};
+
+  #[test]
+  fn parse_durations() {
+    let cases = [
+      ("500ms", Some(Duration::from_millis(500))),
+      ("5s", Some(Duration::from_secs(5))),
+      ("2min", Some(Duration::from_secs(120))),
+      ("1h", Some(Duration::from_secs(3600))),
+      ("0s", Some(Duration::ZERO)),
+      ("0ms", Some(Duration::ZERO)),
+      (
+        "18446744073709551615ms",
+        Some(Duration::from_millis(u64::MAX)),
+      ),
+      ("18446744073709551615s", Some(Duration::from_secs(u64::MAX))),
+      // Overflows
+      ("18446744073709551616ms", None),
+      ("307445734561825861min", None),
+      ("5124095576030432h", None),
+      ("", None),
+      ("5", None),
+      ("s", None),
+      ("5 s", None),
+      (" 5s", None),
+      ("1.5s", None),
+      ("-1s", None),
+      ("+1s", None),
+      ("5S", None),
+      ("5sec", None),
+      ("5d", None),
+    ];
+
+    for (value, duration) in cases {
+      assert_eq!(parse_duration(value), duration, "{value:?}");
+    }
+  }
+
+  #[test]
+  fn format_durations() {
+    let cases = [
+      (Duration::ZERO, "0s"),
+      (Duration::from_nanos(1), "1ms"),
+      (Duration::from_micros(1500), "2ms"),
+      (Duration::from_millis(1500), "1500ms"),
+      (Duration::from_secs(2), "2s"),
+      (Duration::from_secs(90), "90s"),
+      (Duration::from_secs(120), "2min"),
+      (Duration::from_secs(5400), "90min"),
+      (Duration::from_secs(7200), "2h"),
+      (Duration::from_millis(u64::MAX), "18446744073709551615ms"),
+    ];
+
+    for (duration, value) in cases {
+      assert_eq!(format_duration(duration), value, "{duration:?}");
+    }
+  }
+
+  #[test]
+  fn durations_round_trip() {
+    for duration in [
+      Duration::ZERO,
+      Duration::from_millis(1),
+      Duration::from_millis(999),
+      Duration::from_millis(60_001),
+      Duration::from_secs(61),
+      Duration::from_secs(3540),
+      Duration::from_secs(86_400),
+      Duration::from_millis(u64::MAX),
+      Duration::from_secs(u64::MAX / 3600 * 3600),
+    ] {
+      assert_eq!(
+        parse_duration(&format_duration(duration)),
+        Some(duration),
+        "{duration:?}"
+      );
+    }
+  }
+}
@@ -1,6 +1,21 @@
 use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug)]
+use bytes::BytesMut;
+use postgres_types::{accepts, to_sql_checked, FromSql, IsNull, ToSql, Type};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+mod build;
+pub mod compat;
+
+pub use self::build::{build_info_service, BuildInfo, BUILD_INFO_PATH};
+pub use fuzion_commons_macros::build_info;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "PublishedModuleVersion", into = "PublishedModuleVersion")]
 pub struct ModuleVersion(pub String, pub i16, pub i16, pub i16);
 
 impl ModuleVersion {
@@ -9,64 +24,722 @@ impl ModuleVersion {
   }
 
   pub fn as_version(&self) -> Version {
-    Version(self.1, self.2, self.3)
+    Version::new(self.1, self.2, self.3)
+  }
+}
+
+/// How a `ModuleVersion` is published, e.g. `{"module": "fuzion", "version": "1.2.0"}`.
+#[derive(Deserialize, Serialize)]
+struct PublishedModuleVersion {
+  module: String,
+  version: Version,
+}
+
+impl From<ModuleVersion> for PublishedModuleVersion {
+  fn from(value: ModuleVersion) -> Self {
+    PublishedModuleVersion {
+      version: value.as_version(),
+      module: value.0,
+    }
+  }
+}
+
+impl TryFrom<PublishedModuleVersion> for ModuleVersion {
+  type Error = String;
+
+  fn try_from(value: PublishedModuleVersion) -> Result<Self, Self::Error> {
+    match value.version.is_pre_release() || value.version.build.is_some() {
+      true => Err(format!(
+        "module {} has version {}, modules are versioned by major.minor.patch only",
+        value.module, value.version
+      )),
+      false => Ok(ModuleVersion(
+        value.module,
+        value.version.major,
+        value.version.minor,
+        value.version.patch,
+      )),
+    }
+  }
+}
+
+/// A semantic version, `<major>.<minor>.<patch>[-<pre-release>][+<build>]`.
+///
+/// Versions are ordered by precedence: numerically by their components, with a pre-release
+/// below the release itself. Build metadata does not affect precedence, it only breaks ties so
+/// that the ordering agrees with `Eq`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Version {
+  pub major: i16,
+  pub minor: i16,
+  pub patch: i16,
+  /// Dot-separated pre-release identifiers, e.g. `rc.1`.
+  pub pre: Option<String>,
+  /// Dot-separated build metadata, e.g. `20240101.abcdef`.
+  pub build: Option<String>,
+}
+
+impl Version {
+  pub const fn new(major: i16, minor: i16, patch: i16) -> Version {
+    Version {
+      major,
+      minor,
+      patch,
+      pre: None,
+      build: None,
+    }
+  }
+
+  pub fn with_pre(mut self, pre: &str) -> Self {
+    self.pre = Some(pre.into());
+    self
+  }
+
+  pub fn with_build(mut self, build: &str) -> Self {
+    self.build = Some(build.into());
+    self
+  }
+
+  pub fn is_pre_release(&self) -> bool {
+    self.pre.is_some()
+  }
+
+  /// Compares by precedence alone, ignoring build metadata.
+  pub fn cmp_precedence(&self, other: &Version) -> Ordering {
+    (self.major, self.minor, self.patch)
+      .cmp(&(other.major, other.minor, other.patch))
+      .then_with(|| cmp_pre(self.pre.as_deref(), other.pre.as_deref()))
+  }
+}
+
+/// A release ranks above any of its pre-releases.
+fn cmp_pre(a: Option<&str>, b: Option<&str>) -> Ordering {
+  match (a, b) {
+    (None, None) => Ordering::Equal,
+    (None, Some(_)) => Ordering::Greater,
+    (Some(_), None) => Ordering::Less,
+    (Some(a), Some(b)) => cmp_pre_release(a, b),
+  }
+}
+
+/// Identifiers are compared one by one, numeric ones numerically and below alphanumeric ones,
+/// and a shorter list of otherwise equal identifiers comes first.
+fn cmp_pre_release(a: &str, b: &str) -> Ordering {
+  let mut a = a.split('.');
+  let mut b = b.split('.');
+
+  loop {
+    let ordering = match (a.next(), b.next()) {
+      (None, None) => return Ordering::Equal,
+      (None, Some(_)) => return Ordering::Less,
+      (Some(_), None) => return Ordering::Greater,
+      (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+      },
+    };
+
+    if ordering != Ordering::Equal {
+      return ordering;
+    }
+  }
+}
+
+impl Ord for Version {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self
+      .cmp_precedence(other)
+      .then_with(|| self.build.cmp(&other.build))
+  }
+}
+
+impl PartialOrd for Version {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+
+    if let Some(pre) = &self.pre {
+      write!(f, "-{pre}")?;
+    }
+    if let Some(build) = &self.build {
+      write!(f, "+{build}")?;
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Invalid version {0}, expected <major>.<minor>.<patch>[-<pre-release>][+<build>]")]
+pub struct ParseVersionError(pub String);
+
+impl FromStr for Version {
+  type Err = ParseVersionError;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || ParseVersionError(value.into());
+
+    let (value_pre, build) = match value.split_once('+') {
+      Some((rest, build)) => (rest, Some(build)),
+      None => (value, None),
+    };
+    let (numbers, pre) = match value_pre.split_once('-') {
+      Some((numbers, pre)) => (numbers, Some(pre)),
+      None => (value_pre, None),
+    };
+
+    if !pre.is_none_or(identifiers_valid) || !build.is_none_or(identifiers_valid) {
+      return Err(invalid());
+    }
+
+    let mut parts = numbers.split('.').map(|part| {
+      match part.chars().all(|c| c.is_ascii_digit()) && !part.is_empty() {
+        true => part.parse::<i16>().ok(),
+        false => None,
+      }
+    });
+
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+      (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) => Ok(Version {
+        major,
+        minor,
+        patch,
+        pre: pre.map(String::from),
+        build: build.map(String::from),
+      }),
+      _ => Err(invalid()),
+    }
+  }
+}
+
+/// Pre-release and build identifiers are non-empty and made of `[0-9A-Za-z-]`.
+fn identifiers_valid(identifiers: &str) -> bool {
+  identifiers.split('.').all(|identifier| {
+    !identifier.is_empty()
+      && identifier
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+  })
+}
+
+impl Serialize for Version {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for Version {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let value: String = Deserialize::deserialize(deserializer)?;
+
+    value.parse().map_err(de::Error::custom)
+  }
+}
+
+/// Stored as text, e.g. in a `varchar` column.
+impl ToSql for Version {
+  fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+    self.to_string().to_sql(ty, out)
+  }
+
+  accepts!(TEXT, VARCHAR);
+
+  to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Version {
+  fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+    Ok(<&str as FromSql>::from_sql(ty, raw)?.parse()?)
+  }
+
+  accepts!(TEXT, VARCHAR);
+}
+
+/// A version requirement, a comma-separated list of comparators that all have to match, e.g.
+/// `^1.2` or `>=1.0, <2.0`.
+///
+/// Comparators follow Cargo's rules: a bare version is a caret requirement, missing components
+/// are wildcards, and `*` matches any release. Pre-releases only match a comparator naming a
+/// pre-release of the same `major.minor.patch`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VersionReq {
+  comparators: Vec<Comparator>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Op {
+  Exact,
+  Greater,
+  GreaterEq,
+  Less,
+  LessEq,
+  Tilde,
+  Caret,
+}
+
+impl Op {
+  const PREFIXES: [(&'static str, Op); 7] = [
+    (">=", Op::GreaterEq),
+    ("<=", Op::LessEq),
+    (">", Op::Greater),
+    ("<", Op::Less),
+    ("=", Op::Exact),
+    ("~", Op::Tilde),
+    ("^", Op::Caret),
+  ];
+
+  fn as_str(&self) -> &'static str {
+    match self {
+      Op::Exact => "=",
+      Op::Greater => ">",
+      Op::GreaterEq => ">=",
+      Op::Less => "<",
+      Op::LessEq => "<=",
+      Op::Tilde => "~",
+      Op::Caret => "^",
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Comparator {
+  op: Op,
+  major: i16,
+  minor: Option<i16>,
+  patch: Option<i16>,
+  pre: Option<String>,
+}
+
+impl VersionReq {
+  /// Matches any release.
+  pub const STAR: VersionReq = VersionReq {
+    comparators: Vec::new(),
+  };
+
+  pub fn matches(&self, version: &Version) -> bool {
+    self
+      .comparators
+      .iter()
+      .all(|comparator| comparator.matches(version))
+      && (!version.is_pre_release()
+        || self
+          .comparators
+          .iter()
+          .any(|comparator| comparator.allows_pre_release(version)))
   }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Version(pub i16, pub i16, pub i16);
+impl Comparator {
+  fn matches(&self, version: &Version) -> bool {
+    match self.op {
+      Op::Exact => self.matches_exact(version),
+      Op::Greater => self.matches_greater(version),
+      Op::GreaterEq => self.matches_exact(version) || self.matches_greater(version),
+      Op::Less => self.matches_less(version),
+      Op::LessEq => self.matches_exact(version) || self.matches_less(version),
+      Op::Tilde => self.matches_tilde(version),
+      Op::Caret => self.matches_caret(version),
+    }
+  }
+
+  fn allows_pre_release(&self, version: &Version) -> bool {
+    self.pre.is_some()
+      && self.major == version.major
+      && self.minor == Some(version.minor)
+      && self.patch == Some(version.patch)
+  }
+
+  fn cmp_pre(&self, version: &Version) -> Ordering {
+    cmp_pre(version.pre.as_deref(), self.pre.as_deref())
+  }
+
+  fn matches_exact(&self, version: &Version) -> bool {
+    version.major == self.major
+      && self.minor.is_none_or(|minor| version.minor == minor)
+      && self.patch.is_none_or(|patch| version.patch == patch)
+      && self.cmp_pre(version) == Ordering::Equal
+  }
+
+  /// Compares the components the comparator names, `None` when they are all equal.
+  fn cmp_components(&self, version: &Version) -> Option<Ordering> {
+    let components = [
+      Some((version.major, self.major)),
+      self.minor.map(|minor| (version.minor, minor)),
+      self.patch.map(|patch| (version.patch, patch)),
+    ];
+
+    components
+      .into_iter()
+      .flatten()
+      .map(|(version, comparator)| version.cmp(&comparator))
+      .find(|ordering| *ordering != Ordering::Equal)
+  }
+
+  fn matches_greater(&self, version: &Version) -> bool {
+    match self.cmp_components(version) {
+      Some(ordering) => ordering == Ordering::Greater,
+      None => self.patch.is_some() && self.cmp_pre(version) == Ordering::Greater,
+    }
+  }
+
+  fn matches_less(&self, version: &Version) -> bool {
+    match self.cmp_components(version) {
+      Some(ordering) => ordering == Ordering::Less,
+      None => self.patch.is_some() && self.cmp_pre(version) == Ordering::Less,
+    }
+  }
+
+  fn matches_tilde(&self, version: &Version) -> bool {
+    if version.major != self.major || self.minor.is_some_and(|minor| version.minor != minor) {
+      return false;
+    }
 
-impl std::cmp::PartialEq for Version {
-  fn eq(&self, other: &Self) -> bool {
-    self.0 == other.0 && self.1 == other.1 && self.2 == other.2
+    match self.patch {
+      Some(patch) if version.patch != patch => version.patch > patch,
+      _ => self.cmp_pre(version) != Ordering::Less,
+    }
+  }
+
+  fn matches_caret(&self, version: &Version) -> bool {
+    if version.major != self.major {
+      return false;
+    }
+
+    let Some(minor) = self.minor else {
+      return true;
+    };
+
+    let Some(patch) = self.patch else {
+      return match self.major {
+        0 => version.minor == minor,
+        _ => version.minor >= minor,
+      };
+    };
+
+    // The leftmost non-zero component may not change.
+    if self.major > 0 {
+      if version.minor != minor {
+        return version.minor > minor;
+      }
+      if version.patch != patch {
+        return version.patch > patch;
+      }
+    } else if minor > 0 {
+      if version.minor != minor {
+        return false;
+      }
+      if version.patch != patch {
+        return version.patch > patch;
+      }
+    } else if version.minor != minor || version.patch != patch {
+      return false;
+    }
+
+    self.cmp_pre(version) != Ordering::Less
   }
 }
 
-impl std::cmp::PartialOrd for Version {
-  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-    if self == other {
-      Some(Ordering::Equal)
-    } else if self.0 < other.0 || self.1 < other.1 || self.2 < other.2 {
-      Some(Ordering::Less)
-    } else {
-      Some(Ordering::Greater)
+impl fmt::Display for VersionReq {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.comparators.is_empty() {
+      return f.write_str("*");
+    }
+
+    for (idx, comparator) in self.comparators.iter().enumerate() {
+      if idx > 0 {
+        f.write_str(", ")?;
+      }
+
+      write!(f, "{}{}", comparator.op.as_str(), comparator.major)?;
+
+      if let Some(minor) = comparator.minor {
+        write!(f, ".{minor}")?;
+      }
+      if let Some(patch) = comparator.patch {
+        write!(f, ".{patch}")?;
+      }
+      if let Some(pre) = &comparator.pre {
+        write!(f, "-{pre}")?;
+      }
     }
+
+    Ok(())
+  }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Invalid version requirement {0}, expected e.g. ^1.2 or >=1.0, <2.0")]
+pub struct ParseVersionReqError(pub String);
+
+impl FromStr for VersionReq {
+  type Err = ParseVersionReqError;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || ParseVersionReqError(value.into());
+
+    if value.trim() == "*" {
+      return Ok(VersionReq::STAR);
+    }
+
+    let comparators = value
+      .split(',')
+      .map(|comparator| parse_comparator(comparator.trim()).ok_or_else(invalid))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(VersionReq { comparators })
+  }
+}
+
+fn parse_comparator(value: &str) -> Option<Comparator> {
+  let (op, rest) = Op::PREFIXES
+    .into_iter()
+    .find_map(|(prefix, op)| Some((op, value.strip_prefix(prefix)?)))
+    .unwrap_or((Op::Caret, value));
+
+  let rest = rest.trim_start();
+  let (numbers, pre) = match rest.split_once('-') {
+    Some((numbers, pre)) => (numbers, Some(pre)),
+    None => (rest, None),
+  };
+
+  let mut parts = numbers.split('.').map(|part| match part {
+    "*" | "x" | "X" => Some(None),
+    part if !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()) => {
+      part.parse::<i16>().ok().map(Some)
+    }
+    _ => None,
+  });
+
+  let major = parts.next()??;
+  let minor = parts.next().unwrap_or(Some(None))?;
+  let patch = parts.next().unwrap_or(Some(None))?;
+
+  if parts.next().is_some()
+    || major.is_none()
+    || (minor.is_none() && patch.is_some())
+    || (patch.is_none() && pre.is_some())
+    || !pre.is_none_or(identifiers_valid)
+  {
+    return None;
+  }
+
+  Some(Comparator {
+    op,
+    major: major?,
+    minor,
+    patch,
+    pre: pre.map(String::from),
+  })
+}
+
+impl Serialize for VersionReq {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let value: String = Deserialize::deserialize(deserializer)?;
+
+    value.parse().map_err(de::Error::custom)
   }
 }
 
 #[cfg(test)]
 mod module_version {
-  use super::Version;
+  use super::{Version, VersionReq};
 
   #[test]
   fn version_nltngt() {
-    let result1 = Version(1, 1, 1) < Version(1, 1, 1);
-    let result2 = Version(1, 1, 1) > Version(1, 1, 1);
+    let result1 = Version::new(1, 1, 1) < Version::new(1, 1, 1);
+    let result2 = Version::new(1, 1, 1) > Version::new(1, 1, 1);
 
     assert!(!(result1 || result2));
   }
 
+  #[test]
+  fn version_cross_component() {
+    assert!(Version::new(2, 0, 0) > Version::new(1, 5, 5));
+    assert!(Version::new(1, 5, 5) < Version::new(2, 0, 0));
+    assert!(Version::new(1, 2, 0) > Version::new(1, 1, 9));
+    assert!(Version::new(0, 1, 0) > Version::new(0, 0, 9));
+    assert!(Version::new(1, 0, 10) > Version::new(1, 0, 9));
+
+    let mut versions = vec![
+      Version::new(2, 0, 0),
+      Version::new(1, 5, 5),
+      Version::new(1, 10, 0),
+      Version::new(0, 0, 1),
+    ];
+    versions.sort();
+
+    assert_eq!(
+      versions,
+      vec![
+        Version::new(0, 0, 1),
+        Version::new(1, 5, 5),
+        Version::new(1, 10, 0),
+        Version::new(2, 0, 0),
+      ]
+    );
+  }
+
+  #[test]
+  fn version_pre_release() {
+    let ordered = [
+      "1.0.0-alpha",
+      "1.0.0-alpha.1",
+      "1.0.0-alpha.beta",
+      "1.0.0-beta",
+      "1.0.0-beta.2",
+      "1.0.0-beta.11",
+      "1.0.0-rc.1",
+      "1.0.0",
+    ];
+
+    for pair in ordered.windows(2) {
+      let a: Version = pair[0].parse().unwrap();
+      let b: Version = pair[1].parse().unwrap();
+
+      assert!(a < b, "{a} < {b}");
+    }
+
+    let a: Version = "1.0.0+1".parse().unwrap();
+    let b: Version = "1.0.0+2".parse().unwrap();
+
+    assert_eq!(a.cmp_precedence(&b), std::cmp::Ordering::Equal);
+    assert_ne!(a, b);
+  }
+
+  #[test]
+  fn version_parse_display() {
+    for value in [
+      "1.2.3",
+      "0.0.0",
+      "1.2.3-rc.1",
+      "1.2.3+build.5",
+      "1.2.3-x-y.1+0a",
+    ] {
+      assert_eq!(value.parse::<Version>().unwrap().to_string(), value);
+    }
+
+    for value in [
+      "1.2",
+      "1.2.3.4",
+      "a.b.c",
+      "1.2.3-",
+      "1.2.3+",
+      "1..3",
+      "-1.2.3",
+      "1.2.3-a..b",
+    ] {
+      assert!(value.parse::<Version>().is_err(), "{value}");
+    }
+
+    assert_eq!(
+      "1.2.3-rc.1".parse::<Version>().unwrap(),
+      Version::new(1, 2, 3).with_pre("rc.1")
+    );
+  }
+
+  #[test]
+  fn version_serde() {
+    let version = Version::new(1, 2, 3).with_pre("beta");
+    let json = serde_json::to_string(&version).unwrap();
+
+    assert_eq!(json, "\"1.2.3-beta\"");
+    assert_eq!(serde_json::from_str::<Version>(&json).unwrap(), version);
+    assert!(serde_json::from_str::<Version>("\"1.2\"").is_err());
+  }
+
+  #[test]
+  fn version_req() {
+    let cases = [
+      ("^1.2", "1.2.0", true),
+      ("^1.2", "1.9.9", true),
+      ("^1.2", "2.0.0", false),
+      ("^1.2", "1.1.9", false),
+      ("1.2.3", "1.2.2", false),
+      ("^0.2.3", "0.2.9", true),
+      ("^0.2.3", "0.3.0", false),
+      ("^0.0.3", "0.0.4", false),
+      ("~1.2.3", "1.2.9", true),
+      ("~1.2.3", "1.3.0", false),
+      ("=1.2", "1.2.7", true),
+      (">1.2", "1.2.9", false),
+      (">1.2", "1.3.0", true),
+      ("<=1.2", "1.2.9", true),
+      (">=1.0, <2.0", "1.5.0", true),
+      (">=1.0, <2.0", "2.0.0", false),
+      ("1.*", "1.7.0", true),
+      ("*", "12.0.0", true),
+      ("^1.2", "1.3.0-rc.1", false),
+      (">=1.3.0-rc.1", "1.3.0-rc.2", true),
+      (">=1.3.0-rc.1", "1.4.0-rc.1", false),
+      (">=1.3.0-rc.1", "1.3.0", true),
+    ];
+
+    for (req, version, matches) in cases {
+      let req: VersionReq = req.parse().unwrap();
+      let version: Version = version.parse().unwrap();
+
+      assert_eq!(req.matches(&version), matches, "{req} {version}");
+    }
+
+    for req in ["", "1.2.3.4", ">=", "^1.*.3", "1.2-rc", "1,", "a"] {
+      assert!(req.parse::<VersionReq>().is_err(), "{req}");
+    }
+
+    let req: VersionReq = ">= 1.0, < 2".parse().unwrap();
+
+    assert_eq!(req.to_string(), ">=1.0, <2");
+    assert_eq!(
+      serde_json::from_str::<VersionReq>("\">=1.0, <2\"").unwrap(),
+      req
+    );
+  }
+
   #[cfg(test)]
   mod major {
     use super::Version;
 
     #[test]
     fn version_major_eq() {
-      let result = Version(1, 1, 1) == Version(1, 1, 1);
+      let result = Version::new(1, 1, 1) == Version::new(1, 1, 1);
 
       assert!(result);
     }
 
     #[test]
     fn version_major_lt() {
-      let result = Version(0, 1, 1) < Version(1, 1, 1);
+      let result = Version::new(0, 1, 1) < Version::new(1, 1, 1);
 
       assert!(result);
     }
 
     #[test]
     fn version_major_gt() {
-      let result = Version(2, 1, 1) > Version(1, 1, 1);
+      let result = Version::new(2, 1, 1) > Version::new(1, 1, 1);
 
       assert!(result);
     }
@@ -78,21 +751,21 @@ mod module_version {
 
     #[test]
     fn version_minor_eq() {
-      let result = Version(1, 1, 1) == Version(1, 1, 1);
+      let result = Version::new(1, 1, 1) == Version::new(1, 1, 1);
 
       assert!(result);
     }
 
     #[test]
     fn version_minor_lt() {
-      let result = Version(1, 0, 1) < Version(1, 1, 1);
+      let result = Version::new(1, 0, 1) < Version::new(1, 1, 1);
 
       assert!(result);
     }
 
     #[test]
     fn version_minor_gt() {
-      let result = Version(1, 2, 1) > Version(1, 1, 1);
+      let result = Version::new(1, 2, 1) > Version::new(1, 1, 1);
 
       assert!(result);
     }
@@ -104,21 +777,21 @@ mod module_version {
 
     #[test]
     fn version_patch_eq() {
-      let result = Version(1, 1, 1) == Version(1, 1, 1);
+      let result = Version::new(1, 1, 1) == Version::new(1, 1, 1);
 
       assert!(result);
     }
 
     #[test]
     fn version_patch_lt() {
-      let result = Version(1, 1, 0) < Version(1, 1, 1);
+      let result = Version::new(1, 1, 0) < Version::new(1, 1, 1);
 
       assert!(result);
     }
 
     #[test]
     fn version_patch_gt() {
-      let result = Version(1, 1, 2) > Version(1, 1, 1);
+      let result = Version::new(1, 1, 2) > Version::new(1, 1, 1);
 
       assert!(result);
     }
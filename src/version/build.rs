@@ -0,0 +1,62 @@
+//! What a running service was built from, captured by `build_info!` and served at
+//! `BUILD_INFO_PATH`.
+//!
+//! ```ignore
+//! let build_info = fuzion_commons::version::build_info!();
+//!
+//! fuzion_commons::logging::log_build_info(&build_info);
+//!
+//! HttpServer::new(move || App::new().service(build_info_service(build_info.clone())))
+//! ```
+
+use std::fmt;
+
+use actix_web::{web, HttpResponse, Resource};
+
+use super::ModuleVersion;
+
+pub const BUILD_INFO_PATH: &str = "/version";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BuildInfo {
+  pub crate_name: &'static str,
+  pub version: ModuleVersion,
+  /// `None` when built outside of a git work tree.
+  pub git_commit: Option<&'static str>,
+  /// Whether tracked files had uncommitted changes.
+  pub git_dirty: Option<bool>,
+  pub rustc_version: Option<&'static str>,
+  /// UTC, e.g. `2024-05-01T12:00:00Z`.
+  pub built_at: &'static str,
+}
+
+impl fmt::Display for BuildInfo {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} {}", self.crate_name, self.version.as_version())?;
+
+    match (self.git_commit, self.git_dirty) {
+      (Some(commit), Some(true)) => write!(f, " ({commit}, dirty)")?,
+      (Some(commit), _) => write!(f, " ({commit})")?,
+      (None, _) => {}
+    }
+
+    write!(f, " built {}", self.built_at)?;
+
+    if let Some(rustc_version) = self.rustc_version {
+      write!(f, " with {rustc_version}")?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Serves `build_info` as JSON at `BUILD_INFO_PATH`.
+pub fn build_info_service(build_info: BuildInfo) -> Resource {
+  web::resource(BUILD_INFO_PATH)
+    .app_data(web::Data::new(build_info))
+    .route(web::get().to(get_build_info))
+}
+
+async fn get_build_info(build_info: web::Data<BuildInfo>) -> HttpResponse {
+  HttpResponse::Ok().json(build_info.get_ref())
+}
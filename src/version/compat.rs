@@ -0,0 +1,195 @@
+//! Publishing a service's module versions, and checking those of a downstream service at
+//! startup.
+//!
+//! ```ignore
+//! App::new().service(versions_service(vec![ModuleVersion::new("billing", 1, 4, 0)]))
+//!
+//! let client = create_http_client();
+//! require_service_version(&client, "http://billing:8080", "billing", &"^1.2".parse()?).await?;
+//! ```
+
+use actix_web::{web, HttpResponse, Resource};
+use awc::error::SendRequestError;
+use thiserror::Error;
+
+use crate::response::{ResponseHandling, ResponseHandlingError};
+
+use super::{ModuleVersion, Version, VersionReq};
+
+pub const VERSIONS_PATH: &str = "/versions";
+
+/// Serves `versions` as JSON at `VERSIONS_PATH`.
+pub fn versions_service(versions: Vec<ModuleVersion>) -> Resource {
+  web::resource(VERSIONS_PATH)
+    .app_data(web::Data::new(versions))
+    .route(web::get().to(get_versions))
+}
+
+async fn get_versions(versions: web::Data<Vec<ModuleVersion>>) -> HttpResponse {
+  HttpResponse::Ok().json(versions.get_ref())
+}
+
+/// Fetches the module versions the service at `base_url` publishes through `versions_service`.
+pub async fn fetch_service_versions(
+  client: &awc::Client,
+  base_url: &str,
+) -> Result<Vec<ModuleVersion>, CompatibilityError> {
+  let url = format!("{}{VERSIONS_PATH}", base_url.trim_end_matches('/'));
+
+  let response = client
+    .get(&url)
+    .send()
+    .await
+    .map_err(|err| CompatibilityError::Request(url.clone(), err))?;
+
+  let mut response = response
+    .handle_error()
+    .await
+    .map_err(|err| CompatibilityError::Response(url.clone(), err))?;
+
+  response
+    .json()
+    .await
+    .map_err(|err| CompatibilityError::Response(url, err.into()))
+}
+
+/// Checks that the service at `base_url` runs a version of `module` matching `required`,
+/// returning the version it runs.
+pub async fn require_service_version(
+  client: &awc::Client,
+  base_url: &str,
+  module: &str,
+  required: &VersionReq,
+) -> Result<Version, CompatibilityError> {
+  let versions = fetch_service_versions(client, base_url).await?;
+
+  let found = versions
+    .iter()
+    .find(|version| version.0 == module)
+    .map(ModuleVersion::as_version)
+    .ok_or_else(|| CompatibilityError::MissingModule {
+      url: base_url.into(),
+      module: module.into(),
+    })?;
+
+  if !required.matches(&found) {
+    return Err(CompatibilityError::Incompatible {
+      url: base_url.into(),
+      module: module.into(),
+      found,
+      required: required.clone(),
+    });
+  }
+
+  info!("[version] {base_url} runs {module} {found}, satisfying {required}");
+
+  Ok(found)
+}
+
+#[derive(Debug, Error)]
+pub enum CompatibilityError {
+  #[error("Could not request {0}: {1}")]
+  Request(String, #[source] SendRequestError),
+  #[error("Invalid response from {0}: {1}")]
+  Response(String, #[source] ResponseHandlingError),
+  #[error("{url} does not publish a version of {module}")]
+  MissingModule { url: String, module: String },
+  #[error("{url} runs {module} {found}, which does not satisfy {required}")]
+  Incompatible {
+    url: String,
+    module: String,
+    found: Version,
+    required: VersionReq,
+  },
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::dev::ServerHandle;
+  use actix_web::{test, App, HttpServer};
+
+  use super::*;
+
+  /// Serves `versions` on a free local port, returning its base URL.
+  fn serve(versions: Vec<ModuleVersion>) -> (String, ServerHandle) {
+    let server = HttpServer::new(move || App::new().service(versions_service(versions.clone())))
+      .workers(1)
+      .bind(("127.0.0.1", 0))
+      .unwrap();
+    let base_url = format!("http://{}/", server.addrs()[0]);
+    let server = server.run();
+    let handle = server.handle();
+
+    actix_web::rt::spawn(server);
+
+    (base_url, handle)
+  }
+
+  fn versions() -> Vec<ModuleVersion> {
+    vec![
+      ModuleVersion::new("fuzion", 0, 3, 1),
+      ModuleVersion::new("billing", 1, 4, 0),
+    ]
+  }
+
+  #[actix_web::test]
+  async fn versions_service_serves_json() {
+    let app = test::init_service(App::new().service(versions_service(versions()))).await;
+    let request = test::TestRequest::get().uri(VERSIONS_PATH).to_request();
+    let served: Vec<ModuleVersion> = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(served, versions());
+  }
+
+  #[actix_web::test]
+  async fn require_service_version_checks_module() {
+    let (base_url, handle) = serve(versions());
+    let client = awc::Client::default();
+
+    assert_eq!(
+      fetch_service_versions(&client, &base_url).await.unwrap(),
+      versions()
+    );
+
+    let found = require_service_version(&client, &base_url, "billing", &"^1.2".parse().unwrap())
+      .await
+      .unwrap();
+    assert_eq!(found, Version::new(1, 4, 0));
+
+    let err = require_service_version(&client, &base_url, "ledger", &"^1.2".parse().unwrap())
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(&err, CompatibilityError::MissingModule { module, .. } if module == "ledger"),
+      "{err:?}"
+    );
+    assert_eq!(
+      err.to_string(),
+      format!("{base_url} does not publish a version of ledger")
+    );
+
+    let err = require_service_version(&client, &base_url, "billing", &"^2".parse().unwrap())
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(&err, CompatibilityError::Incompatible { found, .. } if *found == Version::new(1, 4, 0)),
+      "{err:?}"
+    );
+    assert_eq!(
+      err.to_string(),
+      format!("{base_url} runs billing 1.4.0, which does not satisfy ^2")
+    );
+
+    let err = fetch_service_versions(&client, &format!("{base_url}missing"))
+      .await
+      .unwrap_err();
+    assert!(matches!(err, CompatibilityError::Response(..)), "{err:?}");
+
+    handle.stop(true).await;
+
+    let err = fetch_service_versions(&client, &base_url)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, CompatibilityError::Request(..)), "{err:?}");
+  }
+}